@@ -1,13 +1,32 @@
 use std::time::{Duration, Instant};
 
 use rocket::{
-    delete, fs::NamedFile, get, http::Status, post, put, response::stream::{Event, EventStream}, tokio::time, Route
+    delete,
+    fs::NamedFile,
+    get,
+    http::Status,
+    post, put,
+    response::stream::{Event, EventStream},
+    serde::json::Json,
+    tokio::{task, time},
+    Route,
+};
+use std::collections::BTreeSet;
+
+use bf_itp::{
+    debug::Watchpoint,
+    format::{self, FormatOptions},
+    generate::{self, GenerateOptions},
+    leaderboard::Page,
+    machine::MachineConfig,
+    skill::{self, Challenge, Score, ScoringProfile, Task, TaskReport},
+    snapshot::Snapshot,
 };
 use serde_json::{json, Value};
 
-use crate::{BFError, BFRes, HWCmd, ItpState, GLOBAL_STATE};
+use crate::{hw, BFError, BFRes, Global, HWCmd, ItpState, GLOBAL_STATE};
 
-/// 
+/// all routes of the api, mounted at `/api`
 pub fn get_routes() -> Vec<Route> {
     rocket::routes![
         // data
@@ -19,6 +38,10 @@ pub fn get_routes() -> Vec<Route> {
         get_state,
         get_speed,
         set_speed,
+        get_machine,
+        set_machine,
+        get_edge_accurate,
+        set_edge_accurate,
         // sse
         code_event,
         input_event,
@@ -31,7 +54,43 @@ pub fn get_routes() -> Vec<Route> {
         start,
         pause,
         step,
+        step_back,
+        seek,
         reset,
+        // debug
+        get_breakpoints,
+        set_breakpoints,
+        clear_breakpoints,
+        add_breakpoint,
+        remove_breakpoint,
+        get_hash_breakpoints,
+        set_hash_breakpoints,
+        get_watchpoints,
+        set_watchpoints,
+        clear_watchpoints,
+        get_snapshot,
+        restore_snapshot,
+        get_trace,
+        set_trace,
+        get_traces,
+        download_trace,
+        replay_trace,
+        // challenge
+        create_challenge,
+        get_challenge,
+        submit_challenge,
+        challenge_leaderboard,
+        // leaderboard
+        get_leaderboard,
+        // task
+        get_tasks,
+        get_task,
+        submit_task,
+        task_leaderboard,
+        // tools
+        format_code,
+        minify_code,
+        generate_code,
         // other
         get_examples,
     ]
@@ -87,13 +146,40 @@ pub fn set_speed(speed: String) -> Status {
     let Ok(speed) = speed.parse() else {
         return Status::UnprocessableEntity;
     };
-    if speed < 1 || speed > 100 {
+    if !(1..=100).contains(&speed) {
         return Status::UnprocessableEntity;
     }
     GLOBAL_STATE.get().unwrap().set_speed(speed);
     Status::Ok
 }
 
+#[get("/run/machine")]
+pub fn get_machine() -> Json<MachineConfig> {
+    Json(*GLOBAL_STATE.get().unwrap().machine.read().unwrap())
+}
+
+/// set the machine model used by the digital twin
+///
+/// can only be changed while no run is active
+#[put("/run/machine", data = "<machine>")]
+pub fn set_machine(machine: Json<MachineConfig>) -> BFRes {
+    GLOBAL_STATE.get().unwrap().change_machine(machine.into_inner())
+}
+
+/// whether new runs use the edge-accurate model of the hw interpreter
+#[get("/run/edge-accurate")]
+pub fn get_edge_accurate() -> Value {
+    json!(*GLOBAL_STATE.get().unwrap().edge_accurate.read().unwrap())
+}
+
+/// enable or disable the edge-accurate model
+///
+/// applies to runs started afterwards
+#[put("/run/edge-accurate", data = "<enabled>")]
+pub fn set_edge_accurate(enabled: Json<bool>) {
+    *GLOBAL_STATE.get().unwrap().edge_accurate.write().unwrap() = enabled.into_inner();
+}
+
 /*##############*\
 ##   /api/sse   ##
 \*##############*/
@@ -210,14 +296,16 @@ pub fn start() -> BFRes {
         ItpState::Idle => {
             drop(state);
             if glob.full_code.read().unwrap().is_empty() {
-                Err(BFError::MissingCode)
-            } else {
-                glob.send_hw(HWCmd::StartRun(false));
-                Ok(())
+                return Err(BFError::MissingCode);
             }
+            glob.check_edge_accurate()?;
+            glob.send_hw(HWCmd::StartRun(false));
+            Ok(())
         }
         ItpState::Startup => Err(BFError::StillStarting),
-        ItpState::Running { ref mut paused, .. } => {
+        ItpState::Running { ref mut paused, .. }
+        | ItpState::Edge { ref mut paused, .. }
+        | ItpState::Replay { ref mut paused, .. } => {
             if *paused {
                 *paused = false;
                 Ok(())
@@ -235,7 +323,9 @@ pub fn pause() -> BFRes {
     match *glob.state.write().unwrap() {
         ItpState::Idle => Err(BFError::ItpNotRunning),
         ItpState::Startup => Err(BFError::ItpNotRunning),
-        ItpState::Running { ref mut paused, .. } => {
+        ItpState::Running { ref mut paused, .. }
+        | ItpState::Edge { ref mut paused, .. }
+        | ItpState::Replay { ref mut paused, .. } => {
             if !*paused {
                 *paused = true;
                 Ok(())
@@ -258,19 +348,22 @@ pub fn pause() -> BFRes {
 /// otherwise, an error is returned
 #[post("/ctrl/step", data = "<steps>")]
 pub fn step(steps: Option<String>) -> BFRes {
-    let steps: usize = steps.map(|n| n.parse().ok()).flatten().unwrap_or(1);
+    let steps: usize = steps.and_then(|n| n.parse().ok()).unwrap_or(1);
     let glob = GLOBAL_STATE.get().unwrap();
     match *glob.state.read().unwrap() {
         ItpState::Idle => {
             if glob.full_code.read().unwrap().is_empty() {
                 return Err(BFError::MissingCode);
             }
+            glob.check_edge_accurate()?;
             glob.send_hw(HWCmd::StartRun(true));
             glob.send_hw(HWCmd::ExecStep(steps, true));
             Ok(())
         }
         ItpState::Startup => Err(BFError::StillStarting),
-        ItpState::Running { ref paused, .. } => {
+        ItpState::Running { ref paused, .. }
+        | ItpState::Edge { ref paused, .. }
+        | ItpState::Replay { ref paused, .. } => {
             if *paused {
                 glob.send_hw(HWCmd::ExecStep(steps, true));
                 Ok(())
@@ -282,13 +375,55 @@ pub fn step(steps: Option<String>) -> BFRes {
     }
 }
 
+/// send a time travel command to a paused run
+///
+/// only possible without hw interpreter, as it cannot step back
+fn time_travel(cmd: HWCmd) -> BFRes {
+    if !hw::TWIN_ONLY {
+        return Err(BFError::HardwareAttached);
+    }
+    let glob = GLOBAL_STATE.get().unwrap();
+    match *glob.state.read().unwrap() {
+        ItpState::Idle => Err(BFError::ItpNotRunning),
+        ItpState::Startup => Err(BFError::StillStarting),
+        ItpState::Running { paused, .. } => {
+            if paused {
+                glob.send_hw(cmd);
+                Ok(())
+            } else {
+                Err(BFError::ItpRunning)
+            }
+        }
+        ItpState::Edge { .. } => Err(BFError::EdgeAccurate),
+        ItpState::Replay { .. } => Err(BFError::Replaying),
+        ItpState::Uncontrolled(_) => Err(BFError::ItpUncontrolled),
+    }
+}
+
+/// undo steps
+///
+/// if no number of steps is given, 1 will be used as a default.
+/// the run has to be paused
+#[post("/ctrl/step-back", data = "<steps>")]
+pub fn step_back(steps: Option<String>) -> BFRes {
+    let steps: usize = steps.and_then(|n| n.parse().ok()).unwrap_or(1);
+    time_travel(HWCmd::StepBack(steps))
+}
+
+/// move a paused run to the state after the given number of cycles
+#[post("/ctrl/seek", data = "<cycle>")]
+pub fn seek(cycle: String) -> BFRes {
+    let cycle = cycle.trim().parse().map_err(|_| BFError::InvalidCycle)?;
+    time_travel(HWCmd::Seek(cycle))
+}
+
 #[post("/ctrl/reset")]
 pub fn reset() -> BFRes {
     let glob = GLOBAL_STATE.get().unwrap();
     match *glob.state.read().unwrap() {
         ItpState::Idle => Ok(()),
         ItpState::Startup => Err(BFError::StillStarting),
-        ItpState::Running { .. } => {
+        ItpState::Running { .. } | ItpState::Edge { .. } | ItpState::Replay { .. } => {
             glob.send_hw(HWCmd::Reset);
             Ok(())
         }
@@ -296,6 +431,391 @@ pub fn reset() -> BFRes {
     }
 }
 
+/*################*\
+##   /api/debug   ##
+\*################*/
+
+/// breakpoints as char positions in the code
+#[get("/debug/breakpoints")]
+pub fn get_breakpoints() -> Json<BTreeSet<usize>> {
+    Json(GLOBAL_STATE.get().unwrap().breakpoints.read().unwrap().clone())
+}
+
+/// replace all breakpoints
+///
+/// positions not on a command stop at the next command
+#[put("/debug/breakpoints", data = "<positions>")]
+pub fn set_breakpoints(positions: Json<BTreeSet<usize>>) {
+    GLOBAL_STATE.get().unwrap().set_breakpoints(positions.into_inner());
+}
+
+#[delete("/debug/breakpoints")]
+pub fn clear_breakpoints() {
+    GLOBAL_STATE.get().unwrap().set_breakpoints(BTreeSet::new());
+}
+
+#[put("/debug/breakpoints/<position>")]
+pub fn add_breakpoint(position: usize) {
+    GLOBAL_STATE.get().unwrap().set_breakpoint(position, true);
+}
+
+#[delete("/debug/breakpoints/<position>")]
+pub fn remove_breakpoint(position: usize) {
+    GLOBAL_STATE.get().unwrap().set_breakpoint(position, false);
+}
+
+/// whether `#` in the code acts as a breakpoint
+#[get("/debug/hash-breakpoints")]
+pub fn get_hash_breakpoints() -> Value {
+    json!(*GLOBAL_STATE.get().unwrap().hash_breakpoints.read().unwrap())
+}
+
+#[put("/debug/hash-breakpoints", data = "<enabled>")]
+pub fn set_hash_breakpoints(enabled: Json<bool>) {
+    GLOBAL_STATE.get().unwrap().set_hash_breakpoints(enabled.into_inner());
+}
+
+#[get("/debug/watchpoints")]
+pub fn get_watchpoints() -> Json<Vec<Watchpoint>> {
+    Json(GLOBAL_STATE.get().unwrap().watchpoints.read().unwrap().clone())
+}
+
+/// replace all watchpoints
+///
+/// applies to an active run immediately
+#[put("/debug/watchpoints", data = "<watchpoints>")]
+pub fn set_watchpoints(watchpoints: Json<Vec<Watchpoint>>) {
+    GLOBAL_STATE.get().unwrap().set_watchpoints(watchpoints.into_inner());
+}
+
+#[delete("/debug/watchpoints")]
+pub fn clear_watchpoints() {
+    GLOBAL_STATE.get().unwrap().set_watchpoints(Vec::new());
+}
+
+/// save the current session
+///
+/// includes code, input, output and the state of the digital twin if a run is active
+#[get("/debug/snapshot")]
+pub fn get_snapshot() -> Json<Snapshot> {
+    Json(GLOBAL_STATE.get().unwrap().snapshot())
+}
+
+/// restore a saved session while idle
+///
+/// a saved run is resumed paused
+#[put("/debug/snapshot", data = "<snapshot>")]
+pub fn restore_snapshot(snapshot: Json<Snapshot>) -> BFRes {
+    GLOBAL_STATE.get().unwrap().restore(snapshot.into_inner())
+}
+
+/// whether new runs record a trace
+#[get("/debug/trace")]
+pub fn get_trace() -> Value {
+    json!(*GLOBAL_STATE.get().unwrap().trace.read().unwrap())
+}
+
+/// enable or disable trace recording
+///
+/// applies to runs started afterwards
+#[put("/debug/trace", data = "<enabled>")]
+pub fn set_trace(enabled: Json<bool>) {
+    *GLOBAL_STATE.get().unwrap().trace.write().unwrap() = enabled.into_inner();
+}
+
+/// names of all recorded traces
+#[get("/debug/traces")]
+pub fn get_traces() -> Json<Vec<String>> {
+    Json(GLOBAL_STATE.get().unwrap().traces())
+}
+
+#[get("/debug/traces/<name>")]
+pub async fn download_trace(name: &str) -> Option<NamedFile> {
+    NamedFile::open(Global::trace_path(name)?).await.ok()
+}
+
+/// play back a recorded trace while idle
+///
+/// the replay starts paused and is controlled like a run,
+/// except that it cannot step back
+#[post("/debug/traces/<name>/replay")]
+pub fn replay_trace(name: &str) -> BFRes {
+    GLOBAL_STATE.get().unwrap().start_replay(name)
+}
+
+/*####################*\
+##   /api/challenge   ##
+\*####################*/
+
+/// the named `profile`, or `default` if not given, with its weights replaced by
+/// `code_len_weight` and `cycles_weight` if given
+fn scoring_profile(
+    default: ScoringProfile,
+    profile: Option<&str>,
+    code_len_weight: Option<f64>,
+    cycles_weight: Option<f64>,
+) -> Result<ScoringProfile, BFError> {
+    let mut profile = match profile {
+        Some(name) => ScoringProfile::named(name).ok_or(BFError::InvalidProfile)?,
+        None => default,
+    };
+    profile.code_len_weight = code_len_weight.unwrap_or(profile.code_len_weight);
+    profile.cycles_weight = cycles_weight.unwrap_or(profile.cycles_weight);
+    if !profile.is_valid() {
+        return Err(BFError::InvalidProfile);
+    }
+    Ok(profile)
+}
+
+/// start a challenge with a random target
+///
+/// scored by the named `profile`, balanced by default, with its weights replaced by
+/// `code_len_weight` and `cycles_weight` if given
+#[post("/challenge?<profile>&<code_len_weight>&<cycles_weight>")]
+pub fn create_challenge(
+    profile: Option<&str>,
+    code_len_weight: Option<f64>,
+    cycles_weight: Option<f64>,
+) -> Result<Json<Challenge>, BFError> {
+    let profile = scoring_profile(
+        ScoringProfile::default(),
+        profile,
+        code_len_weight,
+        cycles_weight,
+    )?;
+    Ok(Json(GLOBAL_STATE.get().unwrap().create_challenge(profile)))
+}
+
+#[get("/challenge/<id>")]
+pub fn get_challenge(id: u64) -> Result<Json<Challenge>, BFError> {
+    GLOBAL_STATE.get().unwrap().challenge(id).map(Json)
+}
+
+/// score a program printing the target of a challenge
+///
+/// scored submissions are added to the leaderboard under `name`.
+/// with `load`, a new best submission replaces the code of the interpreter if possible
+#[post("/challenge/<id>/submit?<name>&<load>", data = "<code>")]
+pub async fn submit_challenge(
+    id: u64,
+    code: String,
+    name: Option<&str>,
+    load: Option<bool>,
+) -> Result<Value, BFError> {
+    let glob = GLOBAL_STATE.get().unwrap();
+    let Challenge {
+        target, profile, ..
+    } = glob.challenge(id)?;
+    // scoring may take a while for slow submissions
+    let (submission, goal) = (code.clone(), target.clone());
+    let report = task::spawn_blocking(move || skill::skill_report(&submission, &goal, profile))
+        .await
+        .expect("scoring panicked");
+    match report.score() {
+        Ok(score) => {
+            let stored = store_submission(name, target, code.clone(), score).await;
+            let best = glob.record_submission(id, &code, score)?;
+            let loaded = best && load.unwrap_or(false) && glob.change_code(code).is_ok();
+            Ok(json!({
+                "verdict": "scored",
+                "score": score,
+                "best": best,
+                "loaded": loaded,
+                "stored": stored,
+                "report": report,
+            }))
+        }
+        Err(rejection) => {
+            Ok(json!({"verdict": "rejected", "rejection": rejection, "report": report}))
+        }
+    }
+}
+
+/// add a submission to the leaderboard without blocking the executor on the disk
+///
+/// returns whether it was stored
+async fn store_submission(name: Option<&str>, target: String, code: String, score: Score) -> bool {
+    let name = name.unwrap_or_default().to_string();
+    task::spawn_blocking(move || {
+        GLOBAL_STATE
+            .get()
+            .unwrap()
+            .add_to_leaderboard(&name, &target, &code, score)
+    })
+    .await
+    .expect("storing a submission panicked")
+}
+
+/// leaderboard of the target of a challenge, including submissions to earlier
+/// challenges with the same target
+///
+/// only submissions scored by the same profile are ranked, the one of the challenge
+/// unless given like for [`create_challenge`]
+#[get("/challenge/<id>/leaderboard?<profile>&<code_len_weight>&<cycles_weight>&<offset>&<limit>")]
+pub fn challenge_leaderboard(
+    id: u64,
+    profile: Option<&str>,
+    code_len_weight: Option<f64>,
+    cycles_weight: Option<f64>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> Result<Json<Page>, BFError> {
+    let glob = GLOBAL_STATE.get().unwrap();
+    let challenge = glob.challenge(id)?;
+    let profile = scoring_profile(challenge.profile, profile, code_len_weight, cycles_weight)?;
+    Ok(Json(glob.leaderboard(
+        Some(&challenge.target),
+        &profile,
+        offset.unwrap_or(0),
+        page_limit(limit),
+    )))
+}
+
+/*######################*\
+##   /api/leaderboard   ##
+\*######################*/
+
+/// entries per page if no `limit` is given
+const PAGE_LIMIT: usize = 20;
+/// most entries per page
+const MAX_PAGE_LIMIT: usize = 100;
+
+fn page_limit(limit: Option<usize>) -> usize {
+    limit.unwrap_or(PAGE_LIMIT).min(MAX_PAGE_LIMIT)
+}
+
+/// scored submissions, best first, for one `target` or all of them
+///
+/// only submissions scored by the same profile are ranked, balanced unless given
+/// like for [`create_challenge`]
+#[get("/leaderboard?<target>&<profile>&<code_len_weight>&<cycles_weight>&<offset>&<limit>")]
+pub fn get_leaderboard(
+    target: Option<&str>,
+    profile: Option<&str>,
+    code_len_weight: Option<f64>,
+    cycles_weight: Option<f64>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> Result<Json<Page>, BFError> {
+    let profile = scoring_profile(
+        ScoringProfile::default(),
+        profile,
+        code_len_weight,
+        cycles_weight,
+    )?;
+    let page = GLOBAL_STATE.get().unwrap().leaderboard(
+        target,
+        &profile,
+        offset.unwrap_or(0),
+        page_limit(limit),
+    );
+    Ok(Json(page))
+}
+
+/*###############*\
+##   /api/task   ##
+\*###############*/
+
+/// tasks with test cases, like reversing the input
+#[get("/task")]
+pub fn get_tasks() -> Json<&'static [Task]> {
+    Json(GLOBAL_STATE.get().unwrap().tasks())
+}
+
+#[get("/task/<id>")]
+pub fn get_task(id: &str) -> Result<Json<&'static Task>, BFError> {
+    GLOBAL_STATE.get().unwrap().task(id).map(Json)
+}
+
+/// run a program on every case of a task, it scores if it passes all of them
+///
+/// scored submissions are added to the leaderboard under `name`, with the task name as target
+#[post("/task/<id>/submit?<name>", data = "<code>")]
+pub async fn submit_task(id: &str, code: String, name: Option<&str>) -> Result<Value, BFError> {
+    let glob = GLOBAL_STATE.get().unwrap();
+    let task = glob.task(id)?;
+    let submission = code.clone();
+    let report = task::spawn_blocking(move || skill::score_task(&submission, task))
+        .await
+        .expect("scoring panicked");
+    match report {
+        Ok(TaskReport { skill, cases }) => match skill.score() {
+            Ok(score) => {
+                let stored = store_submission(name, task.name.clone(), code, score).await;
+                Ok(json!({
+                    "verdict": "scored",
+                    "score": score,
+                    "cases": cases,
+                    "stored": stored,
+                    "report": skill,
+                }))
+            }
+            Err(_) => Ok(json!({"verdict": "failed", "cases": cases, "report": skill})),
+        },
+        Err(rejection) => Ok(json!({"verdict": "rejected", "rejection": rejection})),
+    }
+}
+
+/// only submissions scored by the same profile are ranked, the one of the task
+/// unless given like for [`create_challenge`]
+#[get("/task/<id>/leaderboard?<profile>&<code_len_weight>&<cycles_weight>&<offset>&<limit>")]
+pub fn task_leaderboard(
+    id: &str,
+    profile: Option<&str>,
+    code_len_weight: Option<f64>,
+    cycles_weight: Option<f64>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> Result<Json<Page>, BFError> {
+    let glob = GLOBAL_STATE.get().unwrap();
+    let task = glob.task(id)?;
+    let profile = scoring_profile(task.profile, profile, code_len_weight, cycles_weight)?;
+    Ok(Json(glob.leaderboard(
+        Some(&task.name),
+        &profile,
+        offset.unwrap_or(0),
+        page_limit(limit),
+    )))
+}
+
+/*################*\
+##   /api/tools   ##
+\*################*/
+
+/// re-indent code by loop depth, keeping comments in place
+///
+/// `width` and `indent` default to 80 and 4, and are at most 1000 and 16
+#[post("/tools/format?<width>&<indent>", data = "<code>")]
+pub fn format_code(code: String, width: Option<usize>, indent: Option<usize>) -> Result<String, BFError> {
+    let default = FormatOptions::default();
+    let options = FormatOptions {
+        width: width.unwrap_or(default.width),
+        indent: indent.unwrap_or(default.indent),
+    };
+    if !options.is_valid() {
+        return Err(BFError::InvalidFormatOptions);
+    }
+    format::format(&code, options).map_err(BFError::InvalidNesting)
+}
+
+/// strip everything but commands and remove pairs that cancel out
+#[post("/tools/minify", data = "<code>")]
+pub fn minify_code(code: String) -> String {
+    format::minify(&code)
+}
+
+/// a short program printing the text
+///
+/// `cycle_weight` trades code length against cycles, 0 gives the shortest code
+#[post("/tools/generate?<cycle_weight>", data = "<text>")]
+pub fn generate_code(text: Vec<u8>, cycle_weight: Option<f64>) -> String {
+    let default = GenerateOptions::default();
+    let options = GenerateOptions {
+        cycle_weight: cycle_weight.unwrap_or(default.cycle_weight),
+    };
+    generate::generate(&text, options)
+}
+
 /*###########*\
 ##   other   ##
 \*###########*/
@@ -0,0 +1,65 @@
+//! compares the execution engines on a directory of programs
+//!
+//! usage: `bench [dir] [input] [iterations]`, defaults to the `example_programs` of the repo,
+//! `255\n` and 10 iterations. the digital twin only runs once, as it is far slower
+
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use bf_itp::{bytecode::Bytecode, io::BufferIo, machine::MachineConfig, optimized::Prog, Run};
+
+fn time<T>(iterations: u32, mut f: impl FnMut() -> T) -> (Duration, T) {
+    let start = Instant::now();
+    let mut res = f();
+    for _ in 1..iterations {
+        res = f();
+    }
+    (start.elapsed() / iterations, res)
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let dir = PathBuf::from(
+        args.next()
+            .unwrap_or(concat!(env!("CARGO_MANIFEST_DIR"), "/../../example_programs").to_string()),
+    );
+    let input = args.next().unwrap_or("255\n".to_string());
+    let iterations = args
+        .next()
+        .map_or(10, |n| n.parse().expect("invalid iterations"));
+    let config = MachineConfig::default();
+
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .expect("failed to read program dir")
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "b"))
+        .collect();
+    files.sort();
+
+    println!(
+        "{:<24} {:>12} {:>12} {:>12} {:>12}",
+        "program", "cycles", "twin", "optimised", "bytecode"
+    );
+    for path in files {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let code = fs::read_to_string(&path).expect("failed to read program");
+        let Ok(prog) = Prog::new(&code, config) else {
+            println!("{name:<24} invalid program");
+            continue;
+        };
+
+        let (twin, _) = time(1, || {
+            let mut run = Run::new(&code, "", config).unwrap();
+            run.run_io(&mut BufferIo::new(input.as_bytes()))
+        });
+        let (optimised, _) = time(iterations, || prog.run_bytes(input.as_bytes()));
+        let (bytecode, res) = time(iterations, || {
+            Bytecode::new(&prog).run(&mut BufferIo::new(input.as_bytes()))
+        });
+        let cycles = res.map_or("error".to_string(), |res| res.stats.cycles.to_string());
+        println!("{name:<24} {cycles:>12} {twin:>12.2?} {optimised:>12.2?} {bytecode:>12.2?}");
+    }
+}
@@ -0,0 +1,119 @@
+//! command line tools for brainfuck programs, without the server
+//!
+//! usage: `bf <command> [args] [options]`, see [`USAGE`]
+
+use std::{fs, io::Read, process::ExitCode};
+
+use bf_itp::{
+    generate::{generate, GenerateOptions},
+    machine::{MachineConfig, MAX_TAPE_LEN},
+    optimized::Prog,
+    transpile::{transpile, Target},
+};
+
+const USAGE: &str = "usage: bf <command> [args] [options]
+
+commands:
+    run <file>                  run the program on stdin and stdout
+    transpile <c|rust> <file>   print the program as standalone C or Rust source,
+                                reads the program from stdin if the file is `-`
+    generate <file>             print a short program printing the contents of the file,
+                                reads stdin if the file is `-`
+
+options:
+    --tape-len <n>
+    --cell-width <u8|u16|u32>
+    --overflow <wrap|saturate|error>
+    --boundary <wrap|error|grow>
+    --eof <zero|max|unchanged|block>";
+
+/// parse a snake_case enum value the way the api does
+fn parse_value<T: serde::de::DeserializeOwned>(option: &str, value: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| format!("invalid value for {option}: {value}"))
+}
+
+/// split the arguments into positional ones and the machine config
+fn parse_args(args: impl Iterator<Item = String>) -> Result<(Vec<String>, MachineConfig), String> {
+    let mut config = MachineConfig::default();
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg);
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {arg}"))?;
+        match arg.as_str() {
+            "--tape-len" => {
+                config.tape_len = value
+                    .parse()
+                    .map_err(|_| format!("invalid value for {arg}: {value}"))?
+            }
+            "--cell-width" => config.cell_width = parse_value(&arg, &value)?,
+            "--overflow" => config.overflow = parse_value(&arg, &value)?,
+            "--boundary" => config.boundary = parse_value(&arg, &value)?,
+            "--eof" => config.eof = parse_value(&arg, &value)?,
+            _ => return Err(format!("unknown option {arg}")),
+        }
+    }
+    if !config.is_valid() {
+        return Err(format!("the tape needs between 1 and {MAX_TAPE_LEN} cells"));
+    }
+    Ok((positional, config))
+}
+
+fn read_source(path: &str) -> Result<String, String> {
+    String::from_utf8(read_bytes(path)?).map_err(|_| format!("{path} is not valid utf-8"))
+}
+
+fn read_bytes(path: &str) -> Result<Vec<u8>, String> {
+    if path == "-" {
+        let mut source = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut source)
+            .map_err(|err| format!("failed to read stdin: {err}"))?;
+        Ok(source)
+    } else {
+        fs::read(path).map_err(|err| format!("failed to read {path}: {err}"))
+    }
+}
+
+fn run() -> Result<(), String> {
+    let (args, config) = parse_args(std::env::args().skip(1))?;
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["run", path] => {
+            let source =
+                fs::read_to_string(path).map_err(|err| format!("failed to read {path}: {err}"))?;
+            let prog = Prog::new(&source, config).map_err(|err| err.to_string())?;
+            prog.run_stdio()
+                .map_err(|err| format!("run aborted: {err}"))?;
+            Ok(())
+        }
+        ["transpile", target, path] => {
+            let target: Target = parse_value("the target", target)?;
+            let source = read_source(path)?;
+            let prog = Prog::new(&source, config).map_err(|err| err.to_string())?;
+            print!("{}", transpile(&prog, target));
+            Ok(())
+        }
+        ["generate", path] => {
+            let text = read_bytes(path)?;
+            println!("{}", generate(&text, GenerateOptions::default()));
+            Ok(())
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
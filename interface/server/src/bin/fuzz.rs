@@ -0,0 +1,28 @@
+//! differential fuzzing of the optimised engines against the digital twin
+//!
+//! usage: `fuzz [programs] [seed]`, defaults to 100000 programs and a random seed
+
+use std::process::ExitCode;
+
+use bf_itp::fuzz::fuzz;
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let programs = args
+        .next()
+        .map_or(100_000, |n| n.parse().expect("invalid count"));
+    let seed = args
+        .next()
+        .map_or_else(rand::random, |seed| seed.parse().expect("invalid seed"));
+    println!("fuzzing {programs} programs with seed {seed}");
+    match fuzz(seed, programs) {
+        Ok(compared) => {
+            println!("all engines match the twin on {compared} terminating programs");
+            ExitCode::SUCCESS
+        }
+        Err(mismatch) => {
+            println!("{mismatch}");
+            ExitCode::FAILURE
+        }
+    }
+}
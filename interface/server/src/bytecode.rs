@@ -0,0 +1,410 @@
+use super::{
+    io::{BufferIo, Io, RunError},
+    machine::{BoundaryPolicy, MachineConfig, MachineError, OverflowPolicy, Tape},
+    optimized::{
+        apply_loop, loop_cycles, raw_len, BFCommandOpt, Budget, Limits, Outcome, Prog, RunOutput,
+        Stop,
+    },
+    RunStats,
+};
+
+/// a single bytecode instruction
+///
+/// operands are stored inline, except the parts of closed-form loops,
+/// which live in a side table so every op stays the same small size
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Op {
+    Add(isize, i64),
+    /// move the head, with the cycles of the moves it replaces
+    Move(isize, usize),
+    In(isize),
+    Out(isize),
+    /// `[`, continue after the matching `]` if the cell under the head is zero,
+    /// taking the cycles of skipping the loop
+    JumpZero(usize, usize),
+    /// `]`, continue after the matching `[` if the cell under the head is not zero
+    JumpNonZero(usize),
+    SetZero(isize),
+    Set(isize, i64),
+    /// [`BFCommandOpt::ArithLoop`] with its counter at an offset, indexing the loop table
+    Loop(isize, usize),
+    Scan(isize),
+    /// [`BFCommandOpt::Repeat`], indexing the loop table
+    Repeat(usize),
+    Nop(usize),
+}
+
+/// parts, cycles per iteration and source length of a closed-form loop
+#[derive(Debug, Clone)]
+struct LoopInfo {
+    start: usize,
+    end: usize,
+    cycles: usize,
+    len: usize,
+}
+
+/// flat bytecode of an [optimised program](Prog)
+///
+/// jumps are resolved once when compiling, so running only dispatches on [`Op`]s.
+/// on machines that wrap both cells and the head, like the physical interpreter,
+/// the cells are changed directly instead of through the checks of [`Tape`]
+#[derive(Debug, Clone)]
+pub struct Bytecode {
+    pub ops: Vec<Op>,
+    loops: Vec<LoopInfo>,
+    parts: Vec<(isize, i64)>,
+    pub config: MachineConfig,
+}
+
+impl Bytecode {
+    pub fn new(prog: &Prog) -> Self {
+        let mut ops = Vec::with_capacity(prog.code.len());
+        let mut loops = Vec::new();
+        let mut parts = Vec::new();
+        let mut starts = Vec::new();
+        // length of the source before the current op
+        let mut len = 0;
+        let mut add_loop = |loop_parts: &[(isize, i64)], cycles, len| {
+            loops.push(LoopInfo {
+                start: parts.len(),
+                end: parts.len() + loop_parts.len(),
+                cycles,
+                len,
+            });
+            parts.extend_from_slice(loop_parts);
+            loops.len() - 1
+        };
+        for op in &prog.code {
+            let source_len = len;
+            len += raw_len(op);
+            let op = match *op {
+                BFCommandOpt::Add(offset, by) => Op::Add(offset, by),
+                BFCommandOpt::Move(by, moves) => Op::Move(by, moves),
+                BFCommandOpt::In(offset) => Op::In(offset),
+                BFCommandOpt::Out(offset) => Op::Out(offset),
+                BFCommandOpt::LoopStart => {
+                    starts.push((ops.len(), source_len));
+                    // patched at the matching `]`
+                    Op::JumpZero(0, 0)
+                }
+                BFCommandOpt::LoopEnd => {
+                    // the optimiser keeps the nesting of the parsed code
+                    let (start, start_len) = starts.pop().expect("invalid program");
+                    ops[start] = Op::JumpZero(ops.len() + 1, len - start_len);
+                    Op::JumpNonZero(start + 1)
+                }
+                BFCommandOpt::SetZero(offset) => Op::SetZero(offset),
+                BFCommandOpt::Set(offset, value) => Op::Set(offset, value),
+                BFCommandOpt::ArithLoop(offset, ref loop_parts, cycles, len) => {
+                    Op::Loop(offset, add_loop(loop_parts, cycles, len))
+                }
+                BFCommandOpt::Scan(stride) => Op::Scan(stride),
+                BFCommandOpt::Repeat(ref loop_parts, cycles) => {
+                    Op::Repeat(add_loop(loop_parts, cycles, 0))
+                }
+                BFCommandOpt::Nop(cycles) => Op::Nop(cycles),
+            };
+            ops.push(op);
+        }
+        Self {
+            ops,
+            loops,
+            parts,
+            config: prog.config,
+        }
+    }
+
+    pub fn run(&self, io: &mut impl Io) -> Result<RunOutput, RunError> {
+        self.run_limited(io, Limits::NONE).map(|(res, _)| res)
+    }
+
+    /// like [`Prog::run_with_limits`]
+    pub fn run_with_limits(
+        &self,
+        inp: &[u8],
+        limits: Limits,
+    ) -> Result<(RunOutput, Outcome), MachineError> {
+        let mut io = BufferIo::new(inp);
+        let (res, stop) = self
+            .run_limited(&mut io, limits)
+            .map_err(RunError::into_machine)?;
+        Ok((res, stop.with_output(io.out)))
+    }
+
+    fn run_limited(&self, io: &mut impl Io, limits: Limits) -> Result<(RunOutput, Stop), RunError> {
+        if self.config.boundary == BoundaryPolicy::Wrap
+            && self.config.overflow == OverflowPolicy::Wrap
+        {
+            return self.run_wrapping(io, limits);
+        }
+        let budget = Budget::new(limits);
+        let mut stats = RunStats::default();
+        let mut tape = Tape::new(self.config);
+        let mut head = 0_usize;
+        let mut pc = 0_usize;
+        let mut stop = Stop::Finished;
+        while let Some(&op) = self.ops.get(pc) {
+            pc += 1;
+            stats.steps += 1;
+            stats.cycles += match op {
+                Op::Add(offset, by) => {
+                    let idx = tape.offset(head, offset)?;
+                    tape.add(idx, by)?;
+                    by.unsigned_abs() as usize
+                }
+                Op::Move(by, moves) => {
+                    head = tape.offset(head, by)?;
+                    moves
+                }
+                Op::In(offset) => {
+                    let idx = tape.offset(head, offset)?;
+                    let value = match io.read()? {
+                        Some(byte) => {
+                            stats.read += 1;
+                            byte as u32
+                        }
+                        None => self
+                            .config
+                            .eof_value(tape.get(idx))
+                            .ok_or(MachineError::InputExhausted)?,
+                    };
+                    tape.set(idx, value);
+                    1
+                }
+                Op::Out(offset) => {
+                    if !budget.may_write(&stats) {
+                        stop = Stop::OutputLimit;
+                        break;
+                    }
+                    let idx = tape.offset(head, offset)?;
+                    io.write(tape.get(idx) as u8)?;
+                    stats.written += 1;
+                    1
+                }
+                Op::JumpZero(target, skip) => {
+                    if tape.get(head) == 0 {
+                        pc = target;
+                        skip
+                    } else {
+                        1
+                    }
+                }
+                Op::JumpNonZero(target) => {
+                    if tape.get(head) != 0 {
+                        pc = target;
+                    }
+                    1
+                }
+                Op::SetZero(offset) => {
+                    let idx = tape.offset(head, offset)?;
+                    let value = tape.get(idx) as usize;
+                    tape.set(idx, 0);
+                    loop_cycles(value, 2, 3)
+                }
+                Op::Set(offset, new) => {
+                    let idx = tape.offset(head, offset)?;
+                    let value = tape.get(idx) as usize;
+                    tape.set(idx, new as u32);
+                    loop_cycles(value, 2, 3) + new as usize
+                }
+                Op::Loop(offset, i) => {
+                    let info = &self.loops[i];
+                    let idx = tape.offset(head, offset)?;
+                    let parts = &self.parts[info.start..info.end];
+                    let count = apply_loop(&mut tape, idx, parts)?;
+                    loop_cycles(count, info.cycles, info.len)
+                }
+                Op::Scan(stride) => {
+                    let steps;
+                    let max_steps = budget.scan_steps(&stats, stride.unsigned_abs());
+                    (head, steps) = tape.scan(head, stride, max_steps)?;
+                    let stride = stride.unsigned_abs();
+                    loop_cycles(steps, stride + 1, stride + 2)
+                }
+                Op::Repeat(i) => {
+                    let info = &self.loops[i];
+                    let parts = &self.parts[info.start..info.end];
+                    info.cycles * apply_loop(&mut tape, head, parts)?
+                }
+                Op::Nop(cycles) => cycles,
+            };
+            if let Some(limit) = budget.exceeded(&stats) {
+                stop = limit;
+                break;
+            }
+        }
+        io.flush()?;
+        Ok((RunOutput { stats, tape, head }, stop))
+    }
+
+    /// [`Bytecode::run_limited`] on a machine wrapping cells and the head
+    ///
+    /// cell widths are powers of two, so wrapping a cell is masking it,
+    /// and only io and scans can fail
+    fn run_wrapping(
+        &self,
+        io: &mut impl Io,
+        limits: Limits,
+    ) -> Result<(RunOutput, Stop), RunError> {
+        let budget = Budget::new(limits);
+        let mut stats = RunStats::default();
+        let mut tape = Tape::new(self.config);
+        let mask = self.config.cell_width.max();
+        let len = tape.len();
+        let wrap = |head: usize, offset: isize| match head.checked_add_signed(offset) {
+            Some(idx) if idx < len => idx,
+            _ => (head as isize + offset).rem_euclid(len as isize) as usize,
+        };
+        let mut head = 0_usize;
+        let mut pc = 0_usize;
+        let mut stop = Stop::Finished;
+        while let Some(&op) = self.ops.get(pc) {
+            let cells = tape.cells_mut();
+            pc += 1;
+            stats.steps += 1;
+            stats.cycles += match op {
+                Op::Add(offset, by) => {
+                    let idx = wrap(head, offset);
+                    cells[idx] = (cells[idx] as i64).wrapping_add(by) as u32 & mask;
+                    by.unsigned_abs() as usize
+                }
+                Op::Move(by, moves) => {
+                    head = wrap(head, by);
+                    moves
+                }
+                Op::In(offset) => {
+                    let idx = wrap(head, offset);
+                    cells[idx] = match io.read()? {
+                        Some(byte) => {
+                            stats.read += 1;
+                            byte as u32 & mask
+                        }
+                        None => self
+                            .config
+                            .eof_value(cells[idx])
+                            .ok_or(MachineError::InputExhausted)?,
+                    };
+                    1
+                }
+                Op::Out(offset) => {
+                    if !budget.may_write(&stats) {
+                        stop = Stop::OutputLimit;
+                        break;
+                    }
+                    io.write(cells[wrap(head, offset)] as u8)?;
+                    stats.written += 1;
+                    1
+                }
+                Op::JumpZero(target, skip) => {
+                    if cells[head] == 0 {
+                        pc = target;
+                        skip
+                    } else {
+                        1
+                    }
+                }
+                Op::JumpNonZero(target) => {
+                    if cells[head] != 0 {
+                        pc = target;
+                    }
+                    1
+                }
+                Op::SetZero(offset) => {
+                    let idx = wrap(head, offset);
+                    let value = cells[idx] as usize;
+                    cells[idx] = 0;
+                    loop_cycles(value, 2, 3)
+                }
+                Op::Set(offset, new) => {
+                    let idx = wrap(head, offset);
+                    let value = cells[idx] as usize;
+                    cells[idx] = new as u32 & mask;
+                    loop_cycles(value, 2, 3) + new as usize
+                }
+                Op::Loop(offset, i) => {
+                    let info = &self.loops[i];
+                    let idx = wrap(head, offset);
+                    let count = cells[idx];
+                    if count != 0 {
+                        for &(offset, value) in &self.parts[info.start..info.end] {
+                            let part = wrap(idx, offset);
+                            let by = value.wrapping_mul(count as i64);
+                            cells[part] = (cells[part] as i64).wrapping_add(by) as u32 & mask;
+                        }
+                        cells[idx] = 0;
+                    }
+                    loop_cycles(count as usize, info.cycles, info.len)
+                }
+                Op::Scan(stride) => {
+                    let steps;
+                    let max_steps = budget.scan_steps(&stats, stride.unsigned_abs());
+                    (head, steps) = tape.scan(head, stride, max_steps)?;
+                    let stride = stride.unsigned_abs();
+                    loop_cycles(steps, stride + 1, stride + 2)
+                }
+                Op::Repeat(i) => {
+                    let info = &self.loops[i];
+                    let count = cells[head];
+                    if count != 0 {
+                        for &(offset, value) in &self.parts[info.start..info.end] {
+                            let part = wrap(head, offset);
+                            let by = value.wrapping_mul(count as i64);
+                            cells[part] = (cells[part] as i64).wrapping_add(by) as u32 & mask;
+                        }
+                        cells[head] = 0;
+                    }
+                    info.cycles * count as usize
+                }
+                Op::Nop(cycles) => cycles,
+            };
+            if let Some(limit) = budget.exceeded(&stats) {
+                stop = limit;
+                break;
+            }
+        }
+        io.flush()?;
+        Ok((RunOutput { stats, tape, head }, stop))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{io::BufferIo, machine::CellWidth};
+
+    #[test]
+    fn matches_prog() {
+        let config = MachineConfig::default();
+        let wide = MachineConfig {
+            cell_width: CellWidth::U16,
+            ..config
+        };
+        // not wrapping cells, so not taking the fast path
+        let saturate = MachineConfig {
+            overflow: OverflowPolicy::Saturate,
+            ..config
+        };
+        for config in [config, wide, saturate] {
+            for (code, inp) in [
+                ("++++[>++++++++[>++++<-]<-]>>.", ""),
+                (",[.,]", "abc"),
+                ("+>+>+>>+<<<<[>]<[<]>>>[>>]<[<<<]", ""),
+                (",[->+>+++<<]>[-]+++[<]>>.", "\x05"),
+                // loops and moves left of cell 0
+                (",[-<+++>>-<]<.>>.<<[-]-.", "\x60"),
+            ] {
+                let prog = Prog::new(code, config).unwrap();
+                let mut io = BufferIo::new(inp.as_bytes());
+                let res = Bytecode::new(&prog).run(&mut io).unwrap();
+                let (stats, out) = prog.run_bytes(inp.as_bytes()).unwrap();
+                assert_eq!((res.stats.cycles, io.out), (stats.cycles, out), "{code}");
+            }
+        }
+        let prog = Prog::new(",[->+>+++<<]>>+", config).unwrap();
+        let res = Bytecode::new(&prog)
+            .run(&mut BufferIo::new(b"\x05"))
+            .unwrap();
+        assert_eq!(res.head, 2);
+        assert_eq!(res.tape.window(1, 1), vec![0, 5, 16]);
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use super::Span;
+
+/// why the [digital twin](crate::Run) paused itself
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PauseReason {
+    /// reached a breakpoint, the command at `pc` has not been executed yet
+    Breakpoint { pc: usize, source: Option<Span> },
+    /// the command at `pc` triggered a watchpoint
+    Watchpoint {
+        pc: usize,
+        source: Option<Span>,
+        watchpoint: Watchpoint,
+    },
+}
+
+/// pauses a run on changes of the tape or the head
+///
+/// ranges are given as `start..end`, end is exclusive
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Watchpoint {
+    /// the cell is written
+    Write { cell: usize },
+    /// the cell is written and changes to the value
+    Value { cell: usize, value: u32 },
+    /// the head moves into the range
+    Enter { start: usize, end: usize },
+    /// the head moves out of the range
+    Leave { start: usize, end: usize },
+}
+
+impl Watchpoint {
+    /// whether a step triggers the watchpoint
+    ///
+    /// `head` holds the head before and after the step,
+    /// `write` the written cell with its old and new value
+    pub fn triggered(&self, head: (usize, usize), write: Option<(usize, u32, u32)>) -> bool {
+        match *self {
+            Watchpoint::Write { cell } => write.is_some_and(|(c, _, _)| c == cell),
+            Watchpoint::Value { cell, value } => {
+                write.is_some_and(|(c, old, new)| c == cell && old != value && new == value)
+            }
+            Watchpoint::Enter { start, end } => {
+                !(start..end).contains(&head.0) && (start..end).contains(&head.1)
+            }
+            Watchpoint::Leave { start, end } => {
+                (start..end).contains(&head.0) && !(start..end).contains(&head.1)
+            }
+        }
+    }
+}
+
+/// breakpoints of a run, keyed by program counter
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    pcs: BTreeSet<usize>,
+    /// cycle count of the last hit
+    ///
+    /// prevents stopping at the same breakpoint again right after resuming
+    last_hit: Option<usize>,
+}
+
+impl Breakpoints {
+    pub fn set(&mut self, pcs: impl IntoIterator<Item = usize>) {
+        self.pcs = pcs.into_iter().collect();
+    }
+
+    pub fn contains(&self, pc: usize) -> bool {
+        self.pcs.contains(&pc)
+    }
+
+    /// whether the run should stop at `pc` after `cycles` cycles
+    pub fn should_stop(&self, pc: usize, cycles: usize) -> bool {
+        self.contains(pc) && self.last_hit != Some(cycles)
+    }
+
+    pub fn hit(&mut self, cycles: usize) {
+        self.last_hit = Some(cycles);
+    }
+}
+
+/// char positions of all `#` in the source
+///
+/// `#` is the conventional debug char, stopping before the next command
+pub fn hash_positions(code: &str) -> impl Iterator<Item = usize> + '_ {
+    code.chars()
+        .enumerate()
+        .filter(|(_, c)| *c == '#')
+        .map(|(i, _)| i)
+}
+
+/// state before a single executed step, used to undo it
+///
+/// a step writes at most the cell under the head
+/// and pushes or pops at most one loop address
+#[derive(Debug, Clone)]
+pub(crate) struct Undo {
+    pub pc: usize,
+    pub head: usize,
+    pub cell: u32,
+    pub jumping: Option<usize>,
+    pub stack_len: usize,
+    pub stack_top: Option<usize>,
+    pub ic: usize,
+    pub out_len: usize,
+}
+
+/// undo log of the most recent steps
+///
+/// older steps are dropped, going back further replays the run from the start
+#[derive(Debug, Clone, Default)]
+pub(crate) struct History {
+    entries: VecDeque<Undo>,
+}
+
+impl History {
+    /// number of steps that can be undone without replaying
+    const LEN: usize = 1 << 16;
+
+    pub fn push(&mut self, undo: Undo) {
+        if self.entries.len() == Self::LEN {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(undo);
+    }
+
+    pub fn pop(&mut self) -> Option<Undo> {
+        self.entries.pop_back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,280 @@
+use serde::Serialize;
+
+use super::{
+    machine::TAPE_LEN, parse_with_source_map, BFCommand, CodeView, ParseError, Run, RunView,
+    SourceMap,
+};
+
+/// state of the hw interpreter, see `interpreter/design/README.md`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HwState {
+    /// zeroing the RAM after a reset
+    Startup,
+    Enabled,
+    /// skipping to the matching `]`
+    Jumping,
+    /// waiting for input
+    Input,
+    /// waiting for the output to be taken
+    Output,
+}
+
+/// a clock edge
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+/// registers of the hw interpreter
+///
+/// part of the [`RunView`] of an [`EdgeRun`]
+#[derive(Debug, Clone, Serialize)]
+pub struct Registers {
+    pub state: HwState,
+    /// the last edge, `None` right after a reset
+    pub edge: Option<Edge>,
+    pub ir: u8,
+    pub dr: u8,
+    pub out: u8,
+    pub jp: usize,
+    pub js: usize,
+}
+
+/// edge-accurate model of the hw interpreter
+///
+/// unlike [`Run`], every clock edge is executed on its own, including the
+/// STARTUP phase that zeroes the RAM. a clock cycle is a rising edge followed
+/// by a falling edge, so [`EdgeRun::cycles`] matches the clock pulses of the hw interpreter.
+/// always uses the physical machine model (8 bit cells, [`TAPE_LEN`] cells, blocking input)
+#[derive(Debug, Clone)]
+pub struct EdgeRun {
+    code: Vec<BFCommand>,
+    pub source_map: SourceMap,
+    ram: Vec<u8>,
+    state: HwState,
+    edge: Option<Edge>,
+    pc: usize,
+    dp: usize,
+    /// the `Max(DP)` signal, set once DP wrapped around
+    dp_max: bool,
+    ir: u8,
+    dr: u8,
+    out_reg: u8,
+    jp: usize,
+    js: usize,
+    /// loop addresses, indexed by `JP`
+    jump_mem: Vec<usize>,
+    pub inp: Vec<u8>,
+    pub ic: usize,
+    pub out: Vec<u8>,
+    cycles: usize,
+}
+
+impl EdgeRun {
+    pub fn new(code: &str, input: &str) -> Result<Self, ParseError> {
+        let (code, source_map) = parse_with_source_map(code)?;
+        Ok(Self {
+            code,
+            source_map,
+            ram: vec![0; TAPE_LEN],
+            state: HwState::Startup,
+            edge: None,
+            pc: 0,
+            dp: 0,
+            dp_max: false,
+            ir: 0,
+            dr: 0,
+            out_reg: 0,
+            jp: 0,
+            js: 0,
+            jump_mem: Vec::new(),
+            inp: input.as_bytes().to_vec(),
+            ic: 0,
+            out: Vec::new(),
+            cycles: 0,
+        })
+    }
+
+    pub fn state(&self) -> HwState {
+        self.state
+    }
+
+    /// number of clock cycles, counted on rising edges
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state != HwState::Startup && self.pc >= self.code.len()
+    }
+
+    /// waiting for input that is not available yet
+    pub fn waiting_input(&self) -> bool {
+        self.state == HwState::Input && self.ic >= self.inp.len()
+    }
+
+    /// execute the next edge
+    ///
+    /// returns true when finished
+    pub fn step(&mut self) -> bool {
+        if self.is_finished() {
+            return true;
+        }
+        match self.edge {
+            Some(Edge::Falling) | None => self.rising(),
+            Some(Edge::Rising) => self.falling(),
+        }
+        self.is_finished()
+    }
+
+    /// execute a whole clock cycle
+    ///
+    /// returns true when finished
+    pub fn tick(&mut self) -> bool {
+        self.step();
+        self.step()
+    }
+
+    fn rising(&mut self) {
+        self.edge = Some(Edge::Rising);
+        self.cycles += 1;
+        let d = self.ram[self.dp];
+        match self.state {
+            HwState::Startup => {
+                if self.dp_max {
+                    self.state = HwState::Enabled;
+                    // the first command needs valid registers as well
+                    self.ir = d.wrapping_add(1);
+                    self.dr = d.wrapping_sub(1);
+                }
+            }
+            HwState::Enabled => {
+                self.ir = d.wrapping_add(1);
+                self.dr = d.wrapping_sub(1);
+                if self.code[self.pc] == BFCommand::LoopEnd && d != 0 {
+                    self.pc = self.jump_mem[self.jp];
+                } else {
+                    self.pc += 1;
+                }
+            }
+            // the design only lists `]`, but every skipped command has to advance the pc
+            HwState::Jumping => self.pc += 1,
+            HwState::Input | HwState::Output => {}
+        }
+    }
+
+    fn falling(&mut self) {
+        self.edge = Some(Edge::Falling);
+        // asynchronous signals, the twin pushes input and confirms output immediately
+        let ie = self.state == HwState::Input && self.ic < self.inp.len();
+        let oe = self.state == HwState::Output;
+        let d = self.ram[self.dp];
+        match self.state {
+            HwState::Startup => {
+                self.ram[self.dp] = 0;
+                self.dp += 1;
+                if self.dp == self.ram.len() {
+                    self.dp = 0;
+                    self.dp_max = true;
+                }
+            }
+            HwState::Enabled => match self.code[self.pc] {
+                BFCommand::Right => self.dp = (self.dp + 1) % self.ram.len(),
+                BFCommand::Left => self.dp = (self.dp + self.ram.len() - 1) % self.ram.len(),
+                BFCommand::Inc => self.ram[self.dp] = self.ir,
+                BFCommand::Dec => self.ram[self.dp] = self.dr,
+                BFCommand::Out => {
+                    self.out_reg = d;
+                    self.state = HwState::Output;
+                }
+                BFCommand::In => self.state = HwState::Input,
+                BFCommand::LoopStart => {
+                    // registers update at the same time, so `*JP` and `JS` use the old `JP`
+                    if d == 0 {
+                        self.js = self.jp + 1;
+                        self.state = HwState::Jumping;
+                    } else {
+                        if self.jump_mem.len() <= self.jp {
+                            self.jump_mem.resize(self.jp + 1, 0);
+                        }
+                        self.jump_mem[self.jp] = self.pc;
+                    }
+                    self.jp += 1;
+                }
+                BFCommand::LoopEnd => self.jp -= 1,
+            },
+            HwState::Jumping => match self.code[self.pc] {
+                BFCommand::LoopStart => self.jp += 1,
+                BFCommand::LoopEnd => {
+                    if self.jp == self.js {
+                        self.state = HwState::Enabled;
+                    }
+                    self.jp -= 1;
+                }
+                _ => {}
+            },
+            HwState::Input => {
+                if ie {
+                    self.ram[self.dp] = self.inp[self.ic];
+                    self.ic += 1;
+                    self.state = HwState::Enabled;
+                }
+            }
+            HwState::Output => {
+                if oe {
+                    self.out.push(self.out_reg);
+                    self.state = HwState::Enabled;
+                }
+            }
+        }
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            state: self.state,
+            edge: self.edge,
+            ir: self.ir,
+            dr: self.dr,
+            out: self.out_reg,
+            jp: self.jp,
+            js: self.js,
+        }
+    }
+
+    pub fn view(&self, ctrl_state: &str, run_state: &str) -> RunView {
+        let len = self.ram.len() as isize;
+        let tape = (self.dp as isize - Run::VIEW_SIZE as isize
+            ..=self.dp as isize + Run::VIEW_SIZE as isize)
+            .map(|i| self.ram[i.rem_euclid(len) as usize] as u32)
+            .collect();
+        let jumping = (self.state == HwState::Jumping).then(|| self.jp + 1 - self.js);
+        // loops skipped while jumping are not stored in the jump memory
+        let depth = match jumping {
+            Some(_) => self.js - 1,
+            None => self.jp,
+        };
+        let run_state = match self.state {
+            HwState::Startup => "startup",
+            HwState::Enabled => run_state,
+            HwState::Jumping => "jumping",
+            HwState::Input => "wait_input",
+            HwState::Output => "output_ready",
+        };
+        RunView {
+            tape,
+            head: self.dp,
+            code: CodeView::new(&self.code, &self.source_map, self.pc),
+            ic: self.ic,
+            jumping,
+            stack: self.jump_mem[..depth].to_vec(),
+            cycles: self.cycles,
+            control_state: ctrl_state.to_string(),
+            run_state: run_state.to_string(),
+            pause_reason: None,
+            registers: Some(self.registers()),
+        }
+    }
+}
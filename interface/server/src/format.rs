@@ -0,0 +1,272 @@
+//! layout of brainfuck source code
+
+use serde::Deserialize;
+
+use super::{check_nesting, lex, ParseError};
+
+/// widest line [`FormatOptions::is_valid`] allows
+pub const MAX_WIDTH: usize = 1000;
+
+/// deepest indent per loop level [`FormatOptions::is_valid`] allows
+pub const MAX_INDENT: usize = 16;
+
+/// layout of [`format`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct FormatOptions {
+    /// longest line, longer runs of commands are wrapped
+    pub width: usize,
+    /// spaces per loop level
+    pub indent: usize,
+}
+
+impl FormatOptions {
+    /// width and indent are at most [`MAX_WIDTH`] and [`MAX_INDENT`],
+    /// larger ones only make the formatted code huge
+    pub fn is_valid(&self) -> bool {
+        self.width <= MAX_WIDTH && self.indent <= MAX_INDENT
+    }
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            indent: 4,
+        }
+    }
+}
+
+/// a command or a line of comment text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Command(char),
+    /// trimmed text, `trailing` if it follows a command on the same source line
+    Comment {
+        text: &'a str,
+        trailing: bool,
+    },
+}
+
+fn tokenize(code: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut after_command = false;
+    let mut start = 0;
+    for (i, c) in code.char_indices() {
+        if lex(c).is_some() {
+            push_comment(&mut tokens, &code[start..i], after_command);
+            tokens.push(Token::Command(c));
+            after_command = true;
+            start = i + 1;
+        } else if c == '\n' {
+            push_comment(&mut tokens, &code[start..i], after_command);
+            after_command = false;
+            start = i + 1;
+        }
+    }
+    push_comment(&mut tokens, &code[start..], after_command);
+    tokens
+}
+
+fn push_comment<'a>(tokens: &mut Vec<Token<'a>>, text: &'a str, trailing: bool) {
+    let text = text.trim();
+    if !text.is_empty() {
+        tokens.push(Token::Comment { text, trailing });
+    }
+}
+
+/// builds the formatted code line by line
+struct Writer {
+    options: FormatOptions,
+    res: String,
+    line: String,
+    /// loop depth of the current line
+    line_depth: usize,
+    depth: usize,
+    /// the current line holds a bracket of an expanded loop, which stands alone
+    closed: bool,
+}
+
+impl Writer {
+    /// room for commands on a line at the current depth
+    fn available(&self) -> usize {
+        self.options
+            .width
+            .saturating_sub(self.options.indent.saturating_mul(self.depth))
+            .max(1)
+    }
+
+    fn end_line(&mut self) {
+        if !self.line.is_empty() {
+            let indent = " ".repeat(self.options.indent.saturating_mul(self.line_depth));
+            self.res.push_str(&indent);
+            self.res.push_str(&self.line);
+            self.res.push('\n');
+            self.line.clear();
+        }
+        self.closed = false;
+    }
+
+    /// add commands that are never split across lines
+    fn push(&mut self, commands: &str) {
+        if self.closed || self.line.len() + commands.len() > self.available() {
+            self.end_line();
+        }
+        if self.line.is_empty() {
+            self.line_depth = self.depth;
+        }
+        self.line.push_str(commands);
+    }
+
+    /// add `len` repetitions of `command`, only split if they don't fit on a line of their own
+    fn push_run(&mut self, command: char, len: usize) {
+        if self.line.len() + len > self.available() {
+            self.end_line();
+        }
+        let mut left = len;
+        while left > 0 {
+            let chunk = left.min(self.available());
+            self.push(&command.to_string().repeat(chunk));
+            left -= chunk;
+        }
+    }
+
+    fn comment(&mut self, text: &str, trailing: bool) {
+        if trailing && !self.line.is_empty() {
+            self.line.push(' ');
+        } else {
+            self.end_line();
+            self.line_depth = self.depth;
+        }
+        self.line.push_str(text);
+        self.end_line();
+    }
+}
+
+/// re-indent code by loop depth
+///
+/// loops without nested loops or comments that fit on a line stay inline, any other
+/// loop gets its brackets on their own lines and its body indented by one level.
+/// comments stay in place, either on their own line or after the preceding commands.
+/// lines are wrapped at [`FormatOptions::width`], between runs of the same command if possible
+pub fn format(code: &str, options: FormatOptions) -> Result<String, ParseError> {
+    check_nesting(code)?;
+    let tokens = tokenize(code);
+    let mut writer = Writer {
+        options,
+        res: String::new(),
+        line: String::new(),
+        line_depth: 0,
+        depth: 0,
+        closed: false,
+    };
+    let mut i = 0;
+    while i < tokens.len() {
+        match tokens[i] {
+            Token::Comment { text, trailing } => writer.comment(text, trailing),
+            Token::Command('[') => match inline_loop(&tokens[i..]) {
+                Some(inline) if inline.len() <= writer.available() => {
+                    writer.push(&inline);
+                    i += inline.len();
+                    continue;
+                }
+                _ => {
+                    writer.end_line();
+                    writer.push("[");
+                    writer.closed = true;
+                    writer.depth += 1;
+                }
+            },
+            Token::Command(']') => {
+                writer.end_line();
+                // checked for correct nesting above
+                writer.depth -= 1;
+                writer.push("]");
+                writer.closed = true;
+            }
+            Token::Command(c) => {
+                let len = tokens[i..]
+                    .iter()
+                    .take_while(|&&token| token == Token::Command(c))
+                    .count();
+                writer.push_run(c, len);
+                i += len;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    writer.end_line();
+    Ok(writer.res)
+}
+
+/// the loop starting at the first token, if it has neither nested loops nor comments
+fn inline_loop(tokens: &[Token]) -> Option<String> {
+    let mut res = String::from("[");
+    for token in &tokens[1..] {
+        match *token {
+            Token::Command('[') | Token::Comment { .. } => return None,
+            Token::Command(c) => {
+                res.push(c);
+                if c == ']' {
+                    return Some(res);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// strip everything but the eight commands and remove adjacent pairs that cancel out,
+/// like `+-` or `<>`
+///
+/// removing pairs keeps the output of the program on machines that wrap cells
+/// and the tape, like the default one, but it takes fewer cycles
+pub fn minify(code: &str) -> String {
+    let mut res = String::new();
+    for c in code.chars().filter(|&c| lex(c).is_some()) {
+        match (res.chars().last(), c) {
+            (Some('+'), '-') | (Some('-'), '+') | (Some('<'), '>') | (Some('>'), '<') => {
+                res.pop();
+            }
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats_code() {
+        let options = FormatOptions {
+            width: 16,
+            indent: 2,
+        };
+        let code = "read a number ,[->+<] move it\n>[ print it\n.[-]<+++++++++++++++++++]\nend";
+        let exp = "read a number\n,[->+<] move it\n>\n[ print it\n  .[-]<\n  ++++++++++++++\n  +++++\n]\nend\n";
+        let formatted = format(code, options).unwrap();
+        assert_eq!(formatted, exp);
+        assert_eq!(format(&formatted, options).unwrap(), exp);
+        assert!(format("[[]", options).is_err());
+        let narrow = FormatOptions {
+            width: 4,
+            indent: 0,
+        };
+        assert_eq!(format("..+++.", narrow).unwrap(), "..\n+++.\n");
+        assert!(options.is_valid());
+        assert!(!FormatOptions {
+            indent: MAX_INDENT + 1,
+            ..options
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn minifies_code() {
+        assert_eq!(minify("a +>+-<< b\n[-+.]>-<+"), "+<[.]>-<+");
+        assert_eq!(minify("+<>-"), "");
+    }
+}
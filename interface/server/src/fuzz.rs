@@ -0,0 +1,395 @@
+//! differential fuzzing of the optimised engines against the [digital twin](Run)
+//!
+//! random programs are run on the twin and on every engine in [`ENGINES`],
+//! any difference in the output, final tape, head or cycles is a bug in an engine.
+//! failing programs are shrunk to a minimal case before they are reported
+
+use std::fmt::Display;
+
+use rand::prelude::*;
+
+use super::{
+    bytecode::Bytecode,
+    check_nesting,
+    machine::{BoundaryPolicy, CellWidth, EofPolicy, MachineConfig, MachineError, OverflowPolicy},
+    optimized::{Limits, Outcome, Prog, RunOutput},
+    Run,
+};
+
+/// cycles a fuzzed program may take, longer running programs are skipped
+pub const CYCLE_BUDGET: usize = 20_000;
+
+/// deepest loop nesting of a generated program
+const MAX_DEPTH: usize = 3;
+
+/// loops the optimiser has special cases for, to make sure they are generated
+const IDIOMS: [&str; 9] = [
+    "[-]",
+    "[->+<]",
+    "[->>+++<<]",
+    "[-<+>>--<]",
+    "[>]",
+    "[<<]",
+    "[>+<-]",
+    "[.-]",
+    // opposite adds to one cell, which saturating cells do not cancel
+    "[->-+<]",
+];
+
+/// a random program with about `len` commands and balanced brackets
+///
+/// the program may not terminate, [`check`] skips those after the [`CYCLE_BUDGET`]
+pub fn random_program(rng: &mut impl Rng, len: usize) -> String {
+    let mut code = String::new();
+    push_block(rng, &mut code, len, 0);
+    code
+}
+
+fn push_block(rng: &mut impl Rng, code: &mut String, len: usize, depth: usize) {
+    let mut left = len;
+    while left > 0 {
+        match rng.gen_range(0..12) {
+            0 if depth < MAX_DEPTH => {
+                let inner = rng.gen_range(1..=left);
+                code.push('[');
+                push_block(rng, code, inner, depth + 1);
+                code.push(']');
+                left -= inner;
+            }
+            1 => {
+                let idiom = IDIOMS.choose(rng).unwrap();
+                code.push_str(idiom);
+                left = left.saturating_sub(idiom.len());
+            }
+            _ => {
+                // arithmetic and moves are the most common by far
+                code.push(*b"++--<>><.,".choose(rng).unwrap() as char);
+                left -= 1;
+            }
+        }
+    }
+}
+
+/// a random machine model, with tiny tapes to reach the boundary often
+pub fn random_config(rng: &mut impl Rng) -> MachineConfig {
+    MachineConfig {
+        tape_len: *[1, 2, 3, 8, 64].choose(rng).unwrap(),
+        cell_width: *[CellWidth::U8, CellWidth::U16, CellWidth::U32]
+            .choose(rng)
+            .unwrap(),
+        overflow: *[
+            OverflowPolicy::Wrap,
+            OverflowPolicy::Saturate,
+            OverflowPolicy::Error,
+        ]
+        .choose(rng)
+        .unwrap(),
+        boundary: *[
+            BoundaryPolicy::Wrap,
+            BoundaryPolicy::Error,
+            BoundaryPolicy::Grow,
+        ]
+        .choose(rng)
+        .unwrap(),
+        eof: *[
+            EofPolicy::Zero,
+            EofPolicy::Max,
+            EofPolicy::Unchanged,
+            EofPolicy::Block,
+        ]
+        .choose(rng)
+        .unwrap(),
+    }
+}
+
+/// how a run of a fuzzed program ended, compared between the engines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ending {
+    Finished {
+        out: Vec<u8>,
+        /// run-length encoded without trailing zeros, as grown tapes may differ in length
+        tape: Vec<(u32, usize)>,
+        head: usize,
+        cycles: usize,
+    },
+    Failed(MachineError),
+    /// went past the [`CYCLE_BUDGET`]
+    OutOfFuel,
+}
+
+impl Ending {
+    fn finished(out: Vec<u8>, res: &RunOutput) -> Self {
+        let mut tape = res.tape.encode();
+        if tape.last().is_some_and(|&(value, _)| value == 0) {
+            tape.pop();
+        }
+        Ending::Finished {
+            out,
+            tape,
+            head: res.head,
+            cycles: res.stats.cycles,
+        }
+    }
+
+    fn from_outcome(res: Result<(RunOutput, Outcome), MachineError>) -> Self {
+        match res {
+            Ok((res, Outcome::Finished(out))) => Ending::finished(out, &res),
+            Ok(_) => Ending::OutOfFuel,
+            Err(err) => Ending::Failed(err),
+        }
+    }
+}
+
+/// an engine under test, running an optimised program on the whole input
+pub type Engine = fn(&Prog, &[u8]) -> Ending;
+
+const LIMITS: Limits = Limits {
+    cycles: CYCLE_BUDGET,
+    ..Limits::NONE
+};
+
+/// the engines compared against the twin
+pub const ENGINES: [(&str, Engine); 2] = [
+    ("prog", |prog, inp| {
+        Ending::from_outcome(prog.run_with_limits(inp, LIMITS))
+    }),
+    ("bytecode", |prog, inp| {
+        Ending::from_outcome(Bytecode::new(prog).run_with_limits(inp, LIMITS))
+    }),
+];
+
+/// run the program on the digital twin, which defines the expected behaviour
+pub fn run_twin(code: &str, inp: &str, config: MachineConfig) -> Ending {
+    let mut run = Run::new(code, inp, config).expect("invalid program");
+    if run.code.is_empty() {
+        return Ending::finished(Vec::new(), &run_output(&run));
+    }
+    loop {
+        match run.step() {
+            Ok(true) => break,
+            Ok(false) if run.blocked => return Ending::Failed(MachineError::InputExhausted),
+            Ok(false) => run.pc += 1,
+            Err(err) => return Ending::Failed(err),
+        }
+        if run.cycles() > CYCLE_BUDGET {
+            return Ending::OutOfFuel;
+        }
+    }
+    Ending::finished(run.out.clone(), &run_output(&run))
+}
+
+fn run_output(run: &Run) -> RunOutput {
+    RunOutput {
+        stats: crate::RunStats {
+            cycles: run.cycles(),
+            ..Default::default()
+        },
+        tape: run.tape().clone(),
+        head: run.head(),
+    }
+}
+
+/// an engine that disagrees with the twin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub engine: &'static str,
+    pub code: String,
+    pub inp: String,
+    pub config: MachineConfig,
+    pub expected: Ending,
+    pub got: Ending,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} disagrees with the twin", self.engine)?;
+        writeln!(f, "code:     {}", self.code)?;
+        writeln!(f, "input:    {:?}", self.inp)?;
+        writeln!(f, "config:   {:?}", self.config)?;
+        writeln!(f, "expected: {:?}", self.expected)?;
+        write!(f, "got:      {:?}", self.got)
+    }
+}
+
+/// compare every engine with the twin on one program
+///
+/// programs that run out of fuel on the twin are skipped, returns false for those
+pub fn check(code: &str, inp: &str, config: MachineConfig) -> Result<bool, Box<Mismatch>> {
+    let expected = run_twin(code, inp, config);
+    if expected == Ending::OutOfFuel {
+        return Ok(false);
+    }
+    let prog = Prog::new(code, config).expect("invalid program");
+    for (engine, run) in ENGINES {
+        let got = run(&prog, inp.as_bytes());
+        if got != expected {
+            return Err(Box::new(Mismatch {
+                engine,
+                code: code.to_string(),
+                inp: inp.to_string(),
+                config,
+                expected,
+                got,
+            }));
+        }
+    }
+    Ok(true)
+}
+
+/// the smallest program found by removing parts of `code` while it still `fails`
+///
+/// tries to remove chunks of decreasing size, whole loops and single bracket pairs,
+/// only ever producing balanced programs
+pub fn shrink(code: &str, fails: impl Fn(&str) -> bool) -> String {
+    let mut code = code.to_string();
+    'shrink: loop {
+        let mut size = code.len() / 2;
+        while size > 0 {
+            for start in 0..=code.len() - size {
+                let candidate = format!("{}{}", &code[..start], &code[start + size..]);
+                if check_nesting(&candidate).is_ok() && fails(&candidate) {
+                    code = candidate;
+                    continue 'shrink;
+                }
+            }
+            size /= 2;
+        }
+        // unwrap loops, keeping their body
+        for (start, _) in code.match_indices('[') {
+            let end = matching_end(&code, start);
+            let candidate = format!(
+                "{}{}{}",
+                &code[..start],
+                &code[start + 1..end],
+                &code[end + 1..]
+            );
+            if fails(&candidate) {
+                code = candidate;
+                continue 'shrink;
+            }
+        }
+        return code;
+    }
+}
+
+/// index of the `]` matching the `[` at `start`
+fn matching_end(code: &str, start: usize) -> usize {
+    let mut depth = 0;
+    for (i, c) in code.char_indices().skip(start) {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            _ => continue,
+        }
+        if depth == 0 {
+            return i;
+        }
+    }
+    panic!("unbalanced program")
+}
+
+impl Mismatch {
+    /// shrink the program and then its input, keeping any mismatch
+    pub fn shrink(&self) -> Box<Mismatch> {
+        let config = self.config;
+        let inp = self.inp.clone();
+        let code = shrink(&self.code, |code| check(code, &inp, config).is_err());
+        let inp = shrink(&inp, |inp| check(&code, inp, config).is_err());
+        // unwrap: the shrunk case still fails
+        check(&code, &inp, config).unwrap_err()
+    }
+}
+
+/// check `programs` random programs from `seed`, half of them on a random machine,
+/// returns the number of programs compared, or the first mismatch shrunk to a minimal case
+pub fn fuzz(seed: u64, programs: usize) -> Result<usize, Box<Mismatch>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut compared = 0;
+    for _ in 0..programs {
+        let len = rng.gen_range(1..40);
+        let code = random_program(&mut rng, len);
+        let inp: String = (0..rng.gen_range(0..4))
+            .map(|_| rng.gen_range(0..=127u8) as char)
+            .collect();
+        // the default machine scores skills and gets every optimisation
+        let config = match rng.gen() {
+            true => MachineConfig::default(),
+            false => random_config(&mut rng),
+        };
+        if check(&code, &inp, config).map_err(|mismatch| mismatch.shrink())? {
+            compared += 1;
+        }
+    }
+    Ok(compared)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn engines_match_twin() {
+        match fuzz(0, 2000) {
+            Ok(compared) => assert!(compared > 500, "only {compared} programs terminated"),
+            Err(mismatch) => panic!("{mismatch}"),
+        }
+    }
+
+    #[test]
+    fn found_mismatches() {
+        use crate::machine::TAPE_LEN;
+        let config = |tape_len, boundary, overflow| MachineConfig {
+            tape_len,
+            boundary,
+            overflow,
+            ..MachineConfig::default()
+        };
+        for (code, inp, config) in [
+            // cancelling moves across the end of the tape
+            (
+                "<>",
+                "",
+                config(8, BoundaryPolicy::Error, OverflowPolicy::Wrap),
+            ),
+            (
+                "<<",
+                "",
+                config(8, BoundaryPolicy::Grow, OverflowPolicy::Wrap),
+            ),
+            // offsets -2 and 0 are the same cell
+            (
+                "+<<[-]",
+                "",
+                config(2, BoundaryPolicy::Wrap, OverflowPolicy::Wrap),
+            ),
+            // the twin overflows cell 2 first
+            (
+                ",[->>-<-<]",
+                "\x05",
+                config(TAPE_LEN, BoundaryPolicy::Wrap, OverflowPolicy::Error),
+            ),
+            // saturating cells do not cancel opposite adds in a loop
+            (
+                &format!("+++++[>{}<-]++[->+-<]>.", "+".repeat(51)),
+                "",
+                config(TAPE_LEN, BoundaryPolicy::Wrap, OverflowPolicy::Saturate),
+            ),
+            (
+                ",>,<[->-+<]>.",
+                "\x02\0",
+                config(TAPE_LEN, BoundaryPolicy::Wrap, OverflowPolicy::Saturate),
+            ),
+        ] {
+            if let Err(mismatch) = check(code, inp, config) {
+                panic!("{mismatch}");
+            }
+        }
+    }
+
+    #[test]
+    fn shrinks_programs() {
+        // fails whenever there is a loop containing an output
+        let fails = |code: &str| code.contains("[.");
+        assert_eq!(shrink("+>+[-<[->.+<]>]<.", fails), "[.]");
+    }
+}
@@ -0,0 +1,194 @@
+//! brainfuck programs printing a given text
+
+use serde::Deserialize;
+
+use super::{
+    machine::MachineConfig,
+    optimized::{Limits, Prog},
+    skill::ScoringProfile,
+};
+
+/// most cells set up by the multiplication loop
+const MAX_CELLS: usize = 8;
+
+/// most iterations of the multiplication loop
+const MAX_FACTOR: usize = 16;
+
+/// how [`generate`] weighs cycles against code length
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct GenerateOptions {
+    /// weight of the log of the cycles against the log of the code length, which
+    /// weighs as in [`ScoringProfile::BALANCED`]. 0 gives the shortest code,
+    /// the default the best balanced skill
+    pub cycle_weight: f64,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            cycle_weight: ScoringProfile::BALANCED.cycles_weight,
+        }
+    }
+}
+
+/// a short program printing `text` on a machine with wrapping 8-bit cells
+///
+/// a multiplication loop sets up cells near clusters of the bytes in the text,
+/// every byte is then printed from the cell that is cheapest to move to and adjust.
+/// tries several numbers of cells and loop iterations and keeps the program
+/// with the lowest cost by [`GenerateOptions::cycle_weight`]
+pub fn generate(text: &[u8], options: GenerateOptions) -> String {
+    if text.is_empty() {
+        return String::new();
+    }
+    let cost = |code: &str| {
+        let prog = Prog::new(code, MachineConfig::default()).expect("generated invalid code");
+        let (res, _) = prog
+            .run_with_limits(b"", Limits::NONE)
+            .expect("generated code fails");
+        (prog.len as f64).ln() * ScoringProfile::BALANCED.code_len_weight
+            + (res.stats.cycles as f64).ln() * options.cycle_weight
+    };
+    // printing from a single cell, without a loop
+    let mut best = print(text, String::new(), vec![0], 0);
+    let mut best_cost = cost(&best);
+    let mut distinct = text.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    for cells in 1..=MAX_CELLS.min(distinct.len()) {
+        let centers = cluster(text, cells);
+        for factor in 2..=MAX_FACTOR {
+            let code = print_with_loop(text, &centers, factor);
+            let code_cost = cost(&code);
+            if code_cost < best_cost {
+                best = code;
+                best_cost = code_cost;
+            }
+        }
+    }
+    best
+}
+
+/// set up the cells with a loop running `factor` times, then print the text
+fn print_with_loop(text: &[u8], centers: &[u8], factor: usize) -> String {
+    let steps: Vec<usize> = centers
+        .iter()
+        .map(|&center| (center as f64 / factor as f64).round() as usize)
+        .collect();
+    let mut setup = "+".repeat(factor);
+    setup.push('[');
+    for &step in &steps {
+        setup.push('>');
+        setup.push_str(&"+".repeat(step));
+    }
+    setup.push_str(&"<".repeat(steps.len()));
+    setup.push_str("-]>");
+    // the loop counter ends as a zero cell
+    let cells = std::iter::once(0)
+        .chain(steps.iter().map(|&step| (step * factor) as u8))
+        .collect();
+    print(text, setup, cells, 1)
+}
+
+/// print every byte from the nearest cell after `code` left the tape as `cells`
+fn print(text: &[u8], mut code: String, mut cells: Vec<u8>, mut head: usize) -> String {
+    for &byte in text {
+        // cells wrap, so adjusting by more than 128 is never needed
+        let (cell, diff) = cells
+            .iter()
+            .enumerate()
+            .map(|(cell, &value)| (cell, byte.wrapping_sub(value) as i8))
+            .min_by_key(|&(cell, diff)| cell.abs_diff(head) + diff.unsigned_abs() as usize)
+            .unwrap();
+        let moves = if cell > head { ">" } else { "<" };
+        code.push_str(&moves.repeat(cell.abs_diff(head)));
+        let adjust = if diff > 0 { "+" } else { "-" };
+        code.push_str(&adjust.repeat(diff.unsigned_abs() as usize));
+        code.push('.');
+        cells[cell] = byte;
+        head = cell;
+    }
+    code
+}
+
+/// centers of `count` clusters of the bytes, in the order they are first used
+fn cluster(text: &[u8], count: usize) -> Vec<u8> {
+    let mut sorted = text.to_vec();
+    sorted.sort_unstable();
+    // start from evenly spaced quantiles
+    let mut centers: Vec<f64> = (0..count)
+        .map(|i| sorted[(2 * i + 1) * sorted.len() / (2 * count)] as f64)
+        .collect();
+    let nearest = |centers: &[f64], byte: u8| {
+        (0..centers.len())
+            .min_by(|&a, &b| {
+                (centers[a] - byte as f64)
+                    .abs()
+                    .total_cmp(&(centers[b] - byte as f64).abs())
+            })
+            .unwrap()
+    };
+    for _ in 0..8 {
+        let mut sums = vec![(0.0, 0); count];
+        for &byte in text {
+            let (sum, n) = &mut sums[nearest(&centers, byte)];
+            *sum += byte as f64;
+            *n += 1;
+        }
+        for (center, (sum, n)) in centers.iter_mut().zip(sums) {
+            if n > 0 {
+                *center = sum / n as f64;
+            }
+        }
+    }
+    let mut order = Vec::new();
+    for &byte in text {
+        let center = nearest(&centers, byte);
+        if !order.contains(&center) {
+            order.push(center);
+        }
+    }
+    order
+        .into_iter()
+        .map(|center| centers[center].round() as u8)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn generates_programs() {
+        let run = |code: &str| {
+            let prog = Prog::new(code, MachineConfig::default()).unwrap();
+            let (res, outcome) = prog.run_with_limits(b"", Limits::NONE).unwrap();
+            (outcome.output().to_vec(), prog.len, res.stats.cycles)
+        };
+        let text = b"Hello, World!\n";
+        let code = generate(text, GenerateOptions::default());
+        let (out, len, _) = run(&code);
+        assert_eq!(out, text);
+        // printing from a single cell takes 390 commands
+        assert!(len < 195, "{code}");
+
+        let short = generate(text, GenerateOptions { cycle_weight: 0.0 });
+        let fast = generate(
+            text,
+            GenerateOptions {
+                cycle_weight: 100.0,
+            },
+        );
+        let (short_out, short_len, short_cycles) = run(&short);
+        let (fast_out, fast_len, fast_cycles) = run(&fast);
+        assert_eq!((short_out, fast_out), (text.to_vec(), text.to_vec()));
+        assert!(short_len <= fast_len && fast_cycles <= short_cycles);
+
+        assert_eq!(
+            run(&generate(b"\xff\0\x80", GenerateOptions::default())).0,
+            b"\xff\0\x80"
+        );
+        assert_eq!(generate(b"", GenerateOptions::default()), "");
+    }
+}
@@ -4,7 +4,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use bf_itp::BFCommand;
+use bf_itp::{edge::HwState, BFCommand, Run};
 
 use crate::{HWCmd, HWState, ItpState, GLOBAL_STATE};
 
@@ -24,10 +24,16 @@ macro_rules! not_raspi {
 
 raspi!(mod ports;);
 raspi!(use ports::Ports;);
+raspi!(use bf_itp::{edge::Edge, machine::EofPolicy};);
 not_raspi! {
     struct Ports;
 }
 
+/// whether only the digital twin runs, without a hw interpreter attached
+///
+/// some debugging features cannot be mirrored by the hw interpreter
+pub const TWIN_ONLY: bool = cfg!(not(all(target_arch = "aarch64", target_env = "gnu")));
+
 pub fn start_hw_thread(tx: Receiver<HWCmd>) -> JoinHandle<()> {
     raspi! {
         let mut ports = Ports::new().unwrap();
@@ -56,26 +62,47 @@ pub fn start_hw_thread(tx: Receiver<HWCmd>) -> JoinHandle<()> {
                         thread::sleep(dur);
                         continue;
                     };
-                    run.pc += 1;
+                    if !run.blocked {
+                        run.pc += 1;
+                    }
                     drop(state);
                     *glob.last_change.state.write().unwrap() = Instant::now();
                     thread::sleep(dur);
                 }
+                ItpState::Edge { paused, .. } if !paused => {
+                    // one edge per half of a clock cycle
+                    drop(state);
+                    handle_cmd(HWCmd::ExecStep(1, false), &mut ports);
+                    thread::sleep(speed_tick(*glob.speed.read().unwrap()));
+                }
+                ItpState::Replay { paused, .. } if !paused => {
+                    // a replayed step takes as long as an executed one
+                    drop(state);
+                    handle_cmd(HWCmd::ExecStep(1, false), &mut ports);
+                    thread::sleep(2 * speed_tick(*glob.speed.read().unwrap()));
+                }
                 ItpState::Uncontrolled(ic) => {
                     raspi! {{
                         drop(state);
                         if let Some(outp) = ports.handle_io(|| {
                             let inp = glob.input.read().unwrap();
-                            let res = *inp.as_bytes().get(ic).unwrap_or(&0);
-                            let l = inp.len();
+                            let Some(&res) = inp.as_bytes().get(ic) else {
+                                let machine = *glob.machine.read().unwrap();
+                                // the current cell is unknown without the digital twin,
+                                // so leaving it unchanged can only be done by blocking
+                                return match machine.eof {
+                                    EofPolicy::Unchanged => None,
+                                    _ => machine.eof_value(0).map(|v| v as u8),
+                                };
+                            };
                             drop(inp);
                             let mut state = glob.state.write().unwrap();
                             let ItpState::Uncontrolled(ref mut ic) = *state else {
-                                return res;
+                                return Some(res);
                             };
-                            *ic = (*ic + 1) % (l + 1);
+                            *ic += 1;
                             drop(state);
-                            res
+                            Some(res)
                         }) {
                             glob.output.write().unwrap().push(outp as char);
                         }
@@ -141,8 +168,11 @@ fn handle_cmd(cmd: HWCmd, ports: &mut Ports) {
             // HW: control -> high
             // HW: control reset -> high -> low
             glob.set_state(ItpState::Startup);
-            // wait for arbitrary startup
-            thread::sleep(Duration::from_secs(3));
+            // the edge-accurate model clocks the startup phase itself
+            if !*glob.edge_accurate.read().unwrap() {
+                // wait for arbitrary startup
+                thread::sleep(Duration::from_secs(3));
+            }
             glob.itp_started(paused);
         }
         HWCmd::ExecStep(count, inc_pc) => {
@@ -152,12 +182,13 @@ fn handle_cmd(cmd: HWCmd, ports: &mut Ports) {
             let mut state = glob.state.write().unwrap();
             for i in 0..count {
                 match *state {
-                    ItpState::Running { ref mut run, .. } => {
+                    ItpState::Running { ref mut run, ref mut paused } => {
                         raspi! {{
-                            ports.handle_io(|| {
-                                *run.inp.get(run.ic).unwrap_or(&0)
-                            });
-                            ports.control.step();
+                            // the hw interpreter has to stay on the breakpoint as well
+                            if !run.at_breakpoint() {
+                                ports.handle_io(|| run.next_input().map(|v| v as u8));
+                                ports.control.step();
+                            }
                         }}
                         let old_out_len = run.out.len();
                         if run.jumping.is_none() && run.pc < run.code.len() {
@@ -168,7 +199,19 @@ fn handle_cmd(cmd: HWCmd, ports: &mut Ports) {
                             };
                             *glob.hw_state.write().unwrap() = hw_state;
                         }
-                        let finished = run.step();
+                        let finished = run.step().unwrap_or_else(|err| {
+                            eprintln!("run aborted: {err}");
+                            true
+                        });
+                        if let Some(reason) = &run.pause_reason {
+                            println!("run paused: {reason:?}");
+                            *paused = true;
+                            *glob.last_change.state.write().unwrap() = Instant::now();
+                        }
+                        if run.blocked {
+                            // keep the pc on `,` or the breakpoint until the run can continue
+                            break;
+                        }
                         if i + 1 < count || inc_pc {
                             run.pc += 1;
                         }
@@ -183,14 +226,103 @@ fn handle_cmd(cmd: HWCmd, ports: &mut Ports) {
                             println!("run finished");
                             break;
                         }
+                        if run.pause_reason.is_some() {
+                            // a watchpoint triggered after executing the step
+                            break;
+                        }
+                    }
+                    ItpState::Edge { ref mut run, .. } => {
+                        raspi! {{
+                            let registers = run.registers();
+                            let rising = registers.edge != Some(Edge::Rising);
+                            if !rising {
+                                // the edges are driven below, so the exchange must not clock
+                                let out = ports.handle_io_unclocked(|| run.inp.get(run.ic).copied());
+                                // the model takes the output on this falling edge as well
+                                if let Some(out) = out {
+                                    if registers.state != HwState::Output || out != registers.out {
+                                        eprintln!("hw interpreter printed {out}, the model did not");
+                                    }
+                                }
+                            }
+                            ports.control.set_clock(rising);
+                        }}
+                        let old_out_len = run.out.len();
+                        let finished = run.step();
+                        *glob.hw_state.write().unwrap() = match run.state() {
+                            HwState::Input => HWState::WaitInput,
+                            HwState::Output => HWState::OutputReady,
+                            _ => HWState::Regular,
+                        };
+                        *glob.last_change.state.write().unwrap() = Instant::now();
+                        if old_out_len != run.out.len() {
+                            glob.set_output(String::from_utf8_lossy(&run.out).into_owned());
+                        }
+                        if finished {
+                            drop(state);
+                            *glob.hw_state.write().unwrap() = HWState::Regular;
+                            glob.set_state(ItpState::Idle);
+                            println!("run finished");
+                            break;
+                        }
+                    }
+                    ItpState::Replay { ref mut replay, .. } => {
+                        let old_out_len = replay.out.len();
+                        let finished = !replay.step().unwrap_or_else(|err| {
+                            eprintln!("replay aborted: {err}");
+                            false
+                        });
+                        *glob.last_change.state.write().unwrap() = Instant::now();
+                        if old_out_len != replay.out.len() {
+                            glob.set_output(String::from_utf8_lossy(&replay.out).into_owned());
+                        }
+                        if finished {
+                            drop(state);
+                            glob.set_state(ItpState::Idle);
+                            println!("replay finished");
+                            break;
+                        }
                     }
                     _ => eprintln!("cannot execute step if itp is not running"),
                 }
             }
         }
+        HWCmd::StepBack(count) => seek(|run| run.cycles().saturating_sub(count)),
+        HWCmd::Seek(cycle) => seek(|_| cycle),
         HWCmd::Reset => {
             // control reset -> high -> low
             glob.set_state(ItpState::Idle);
         }
     }
 }
+
+/// move the digital twin to another cycle
+fn seek(target: impl FnOnce(&Run) -> usize) {
+    let glob = GLOBAL_STATE.get().unwrap();
+    let mut state = glob.state.write().unwrap();
+    let ItpState::Running { ref mut run, .. } = *state else {
+        eprintln!("cannot seek if itp is not running");
+        return;
+    };
+    let cycle = target(run);
+    if cycle < run.cycles() {
+        // seeking back ends the trace, report if it was not written completely
+        if let Err(err) = run.stop_trace() {
+            eprintln!("cannot record trace: {err}");
+        }
+    }
+    let finished = run.seek(cycle).unwrap_or_else(|err| {
+        eprintln!("run aborted: {err}");
+        true
+    });
+    let out = String::from_utf8_lossy(&run.out).into_owned();
+    drop(state);
+    glob.set_output(out);
+    *glob.hw_state.write().unwrap() = HWState::Regular;
+    if finished {
+        glob.set_state(ItpState::Idle);
+        println!("run finished");
+    } else {
+        *glob.last_change.state.write().unwrap() = Instant::now();
+    }
+}
@@ -87,6 +87,11 @@ impl ControlPort {
     pub fn step(&mut self) {
         pulse(&mut self.pins[2]);
     }
+
+    /// drive a single clock edge
+    pub fn set_clock(&mut self, level: bool) {
+        self.pins[2].write(level.into());
+    }
 }
 
 impl ProgramPort {
@@ -143,18 +148,37 @@ impl Ports {
         })
     }
 
-    pub fn handle_io(&mut self, inp: impl FnOnce() -> u8) -> Option<u8> {
+    /// exchange io with the hw interpreter
+    ///
+    /// `inp` returns `None` if no input is available yet,
+    /// in which case the hw interpreter keeps waiting
+    pub fn handle_io(&mut self, inp: impl FnOnce() -> Option<u8>) -> Option<u8> {
+        self.exchange_io(inp, true)
+    }
+
+    /// exchange io like [`Ports::handle_io`], without clocking the hw interpreter afterwards
+    ///
+    /// used when the caller drives every clock edge itself
+    pub fn handle_io_unclocked(&mut self, inp: impl FnOnce() -> Option<u8>) -> Option<u8> {
+        self.exchange_io(inp, false)
+    }
+
+    fn exchange_io(&mut self, inp: impl FnOnce() -> Option<u8>, clock: bool) -> Option<u8> {
         // Input: writing to pins
         if self.io.pins[0].read() == Level::High {
-            self.io.set_pinmode(Mode::Output);
-            let data = bits(inp());
-            for i in 0..8 {
-                self.io.pins[i + 3].write(data[i]);
+            if let Some(data) = inp() {
+                self.io.set_pinmode(Mode::Output);
+                let data = bits(data);
+                for i in 0..8 {
+                    self.io.pins[i + 3].write(data[i]);
+                }
+                // pulse for push input
+                pulse_io(&mut self.io.pins[1]);
+                self.io.set_pinmode(Mode::Input);
+                if clock {
+                    self.control.step();
+                }
             }
-            // pulse for push input
-            pulse_io(&mut self.io.pins[1]);
-            self.io.set_pinmode(Mode::Input);
-            self.control.step();
         }
         // Output: reading from pins
         if self.io.pins[1].read() == Level::High {
@@ -164,7 +188,9 @@ impl Ports {
             }
             // pulse for output confirmed
             pulse_io(&mut self.io.pins[2]);
-            self.control.step();
+            if clock {
+                self.control.step();
+            }
             return Some(byte(val));
         }
         None
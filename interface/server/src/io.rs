@@ -0,0 +1,136 @@
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+};
+
+use super::machine::MachineError;
+
+/// byte-wise input and output of a run
+///
+/// both callbacks may block, e.g. while waiting on a pipe or socket
+pub trait Io {
+    /// the next input byte, `None` once no more input will arrive
+    fn read(&mut self) -> io::Result<Option<u8>>;
+
+    fn write(&mut self, byte: u8) -> io::Result<()>;
+
+    /// called at the end of a run
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// input known up front, collecting the output
+#[derive(Debug, Clone, Default)]
+pub struct BufferIo {
+    pub inp: Vec<u8>,
+    /// number of bytes read
+    pub ic: usize,
+    pub out: Vec<u8>,
+}
+
+impl BufferIo {
+    pub fn new(inp: &[u8]) -> Self {
+        Self {
+            inp: inp.to_vec(),
+            ic: 0,
+            out: Vec::new(),
+        }
+    }
+}
+
+impl Io for BufferIo {
+    fn read(&mut self) -> io::Result<Option<u8>> {
+        let byte = self.inp.get(self.ic).copied();
+        self.ic += byte.is_some() as usize;
+        Ok(byte)
+    }
+
+    fn write(&mut self, byte: u8) -> io::Result<()> {
+        self.out.push(byte);
+        Ok(())
+    }
+}
+
+/// reads and writes byte streams, like stdin and stdout, a pipe or a socket
+///
+/// the output is flushed before every read, so prompts show up
+/// before an interactive program waits for an answer
+#[derive(Debug)]
+pub struct StreamIo<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: Read, W: Write> StreamIo<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self { reader, writer }
+    }
+
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+}
+
+impl<R: Read, W: Write> Io for StreamIo<R, W> {
+    fn read(&mut self) -> io::Result<Option<u8>> {
+        self.writer.flush()?;
+        let mut byte = [0];
+        loop {
+            return match self.reader.read(&mut byte) {
+                Ok(0) => Ok(None),
+                Ok(_) => Ok(Some(byte[0])),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => Err(err),
+            };
+        }
+    }
+
+    fn write(&mut self, byte: u8) -> io::Result<()> {
+        self.writer.write_all(&[byte])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// a run aborted by the machine model or its [`Io`]
+#[derive(Debug)]
+pub enum RunError {
+    Machine(MachineError),
+    Io(io::Error),
+}
+
+impl Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::Machine(err) => write!(f, "{err}"),
+            RunError::Io(err) => write!(f, "io failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+impl From<MachineError> for RunError {
+    fn from(value: MachineError) -> Self {
+        RunError::Machine(value)
+    }
+}
+
+impl From<io::Error> for RunError {
+    fn from(value: io::Error) -> Self {
+        RunError::Io(value)
+    }
+}
+
+impl RunError {
+    /// the machine error of a run that cannot fail on io, like one using a [`BufferIo`]
+    pub fn into_machine(self) -> MachineError {
+        match self {
+            RunError::Machine(err) => err,
+            RunError::Io(err) => unreachable!("infallible io failed: {err}"),
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! scored skill submissions, kept in an append-only json lines log
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::skill::{Score, ScoringProfile};
+
+/// entries ranked per name and target, older and worse ones are only kept in the log
+pub const ENTRIES_PER_NAME: usize = 10;
+
+/// longest code stored, the default limit of rocket for strings
+pub const MAX_CODE_LEN: usize = 8192;
+
+/// a scored submission
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub name: String,
+    /// the printed target, or the name of a task
+    pub target: String,
+    pub code: String,
+    pub code_len: usize,
+    pub cycles: usize,
+    pub skill: f64,
+    /// the skill is only comparable between entries with the same profile
+    #[serde(default)]
+    pub profile: ScoringProfile,
+    /// seconds since the unix epoch
+    pub timestamp: u64,
+}
+
+impl Entry {
+    /// order in a ranking, best first. equal skill is ranked by who submitted first
+    fn cmp_rank(&self, other: &Entry) -> std::cmp::Ordering {
+        other
+            .skill
+            .total_cmp(&self.skill)
+            .then(self.timestamp.cmp(&other.timestamp))
+    }
+
+    pub fn new(name: &str, target: &str, code: &str, score: Score, timestamp: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            target: target.to_string(),
+            code: code.to_string(),
+            code_len: score.code_len,
+            cycles: score.cycles,
+            skill: score.skill,
+            profile: score.profile,
+            timestamp,
+        }
+    }
+}
+
+/// a part of a ranking
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Page {
+    /// number of entries in the whole ranking
+    pub total: usize,
+    pub offset: usize,
+    pub entries: Vec<Entry>,
+}
+
+/// all submissions, loaded from and appended to a log file
+///
+/// appending does not block rankings while the log is written
+#[derive(Debug)]
+pub struct Leaderboard {
+    path: PathBuf,
+    entries: RwLock<Vec<Entry>>,
+    /// held while appending to the log
+    log: Mutex<()>,
+}
+
+impl Leaderboard {
+    /// load the log at `path`, which is created by the first submission
+    ///
+    /// lines that cannot be read, like one cut off by a power loss, are skipped
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let entries = match fs::read_to_string(&path) {
+            Ok(log) => log
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path,
+            entries: RwLock::new(entries),
+            log: Mutex::new(()),
+        })
+    }
+
+    /// append an entry to the log, it is on disk once this returns
+    ///
+    /// returns false without storing it if its code is longer than [`MAX_CODE_LEN`]
+    pub fn add(&self, entry: Entry) -> io::Result<bool> {
+        if entry.code.len() > MAX_CODE_LEN {
+            return Ok(false);
+        }
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let log = self.log.lock().unwrap();
+        let mut file: File = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        self.entries.write().unwrap().push(entry);
+        drop(log);
+        Ok(true)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.read().unwrap().is_empty()
+    }
+
+    /// entries scored by `profile` for `target`, or for all targets, best first
+    ///
+    /// equal skill is ranked by who submitted first.
+    /// only the best [`ENTRIES_PER_NAME`] entries of a name for a target are ranked
+    pub fn ranking(
+        &self,
+        target: Option<&str>,
+        profile: &ScoringProfile,
+        offset: usize,
+        limit: usize,
+    ) -> Page {
+        let entries = self.entries.read().unwrap();
+        let mut ranking: Vec<_> = entries
+            .iter()
+            .filter(|entry| target.map_or(true, |target| entry.target == target))
+            .filter(|entry| entry.profile == *profile)
+            .collect();
+        ranking.sort_by(|a, b| a.cmp_rank(b));
+        let mut ranked = HashMap::new();
+        ranking.retain(|entry| {
+            let count = ranked.entry((&entry.name, &entry.target)).or_insert(0);
+            *count += 1;
+            *count <= ENTRIES_PER_NAME
+        });
+        Page {
+            total: ranking.len(),
+            offset,
+            entries: ranking
+                .into_iter()
+                .skip(offset)
+                .take(limit)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn survives_reopening() {
+        let path = std::env::temp_dir().join(format!("leaderboard-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let board = Leaderboard::open(&path).unwrap();
+        let score = |skill| Score {
+            skill,
+            code_len: 10,
+            cycles: 20,
+            profile: ScoringProfile::BALANCED,
+        };
+        let shortest = Score {
+            profile: ScoringProfile::SHORTEST,
+            ..score(9.0)
+        };
+        board
+            .add(Entry::new("a", "AB", "+.", score(5.0), 1))
+            .unwrap();
+        board
+            .add(Entry::new("b", "AB", "-.", score(7.0), 2))
+            .unwrap();
+        board
+            .add(Entry::new("c", "CD", ".", score(5.0), 3))
+            .unwrap();
+        board.add(Entry::new("d", "AB", "+.", shortest, 4)).unwrap();
+        // a line cut off while writing
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"name\":\"d\",")
+            .unwrap();
+
+        let board = Leaderboard::open(&path).unwrap();
+        assert_eq!(board.len(), 4);
+        let names = |page: Page| page.entries.into_iter().map(|e| e.name).collect::<Vec<_>>();
+        let balanced = &ScoringProfile::BALANCED;
+        assert_eq!(names(board.ranking(None, balanced, 0, 10)), ["b", "a", "c"]);
+        assert_eq!(names(board.ranking(Some("AB"), balanced, 1, 10)), ["a"]);
+        let shortest = &ScoringProfile::SHORTEST;
+        assert_eq!(names(board.ranking(Some("AB"), shortest, 0, 10)), ["d"]);
+        let page = board.ranking(None, balanced, 1, 1);
+        assert_eq!((page.total, names(page)), (3, vec!["a".to_string()]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ranks_the_best_entries() {
+        let path =
+            std::env::temp_dir().join(format!("leaderboard-best-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let board = Leaderboard::open(&path).unwrap();
+        let entry = |name, skill: f64, code: &str| {
+            let score = Score {
+                skill,
+                code_len: code.len(),
+                cycles: 20,
+                profile: ScoringProfile::BALANCED,
+            };
+            Entry::new(name, "AB", code, score, skill as u64)
+        };
+        for skill in 0..30 {
+            assert!(board.add(entry("a", skill as f64, "+.")).unwrap());
+        }
+        assert!(board.add(entry("b", 1.0, "+.")).unwrap());
+        let long = "+".repeat(MAX_CODE_LEN + 1);
+        assert!(!board.add(entry("c", 50.0, &long)).unwrap());
+
+        // every stored submission stays in the log
+        let board = Leaderboard::open(&path).unwrap();
+        assert_eq!(board.len(), 31);
+        let page = board.ranking(Some("AB"), &ScoringProfile::BALANCED, 0, 100);
+        let skills: Vec<_> = page.entries.iter().map(|e| e.skill).collect();
+        let mut expected: Vec<_> = (20..30).rev().map(|s| s as f64).collect();
+        expected.push(1.0);
+        assert_eq!(skills, expected);
+        assert_eq!(page.total, ENTRIES_PER_NAME + 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -2,8 +2,26 @@ use std::fmt::Display;
 
 use serde::Serialize;
 
+pub mod bytecode;
+pub mod debug;
+pub mod edge;
+pub mod format;
+pub mod fuzz;
+pub mod generate;
+pub mod io;
+pub mod leaderboard;
+pub mod machine;
 pub mod optimized;
 pub mod skill;
+pub mod snapshot;
+pub mod trace;
+pub mod transpile;
+
+use debug::{Breakpoints, History, PauseReason, Undo, Watchpoint};
+use io::{Io, RunError};
+use machine::{MachineConfig, MachineError, Tape};
+use snapshot::{RunSnapshot, SnapshotError};
+use trace::{IoEvent, TraceRecord, TraceWriter};
 
 /// A single brainfuck instruction
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -47,51 +65,163 @@ fn lex(c: char) -> Option<BFCommand> {
     }
 }
 
-pub fn is_nesting_correct(code: &str) -> bool {
-    let mut depth = 0;
-    for c in code.chars() {
-        if c == '[' {
-            depth += 1;
-        } else if c == ']' && depth == 0 {
-            return false;
-        } else if c == ']' {
-            depth -= 1;
+/// a `[` or `]` without a partner
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+pub struct UnmatchedBracket {
+    pub bracket: char,
+    /// byte offset into the source
+    pub offset: usize,
+    /// 1-based line
+    pub line: usize,
+    /// 1-based column, counted in chars
+    pub column: usize,
+}
+
+/// code is not correctly nested
+///
+/// lists every unmatched bracket, ordered by position
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct ParseError {
+    pub unmatched: Vec<UnmatchedBracket>,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "code is not correctly nested:")?;
+        for b in &self.unmatched {
+            write!(f, " unmatched '{}' at {}:{}", b.bracket, b.line, b.column)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// check that all loops are correctly nested
+pub fn check_nesting(code: &str) -> Result<(), ParseError> {
+    let mut unmatched = Vec::new();
+    let mut open = Vec::new();
+    let (mut line, mut column) = (1, 1);
+    for (offset, c) in code.char_indices() {
+        let bracket = UnmatchedBracket {
+            bracket: c,
+            offset,
+            line,
+            column,
+        };
+        match c {
+            '[' => open.push(bracket),
+            ']' if open.pop().is_none() => unmatched.push(bracket),
+            _ => {}
+        }
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
         }
     }
-    depth == 0
+    if unmatched.is_empty() && open.is_empty() {
+        return Ok(());
+    }
+    unmatched.append(&mut open);
+    unmatched.sort_by_key(|b| b.offset);
+    Err(ParseError { unmatched })
+}
+
+/// range of chars in the source, end is exclusive
+///
+/// counted in chars (not bytes), so the client can index the source directly
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// maps every parsed [`BFCommand`] back to its position in the source
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SourceMap {
+    spans: Vec<Span>,
+}
+
+impl SourceMap {
+    /// span of the command at `pc`
+    pub fn span(&self, pc: usize) -> Option<Span> {
+        self.spans.get(pc).copied()
+    }
+
+    /// index of the first command at or after the char position `pos` of the source
+    pub fn next_pc(&self, pos: usize) -> Option<usize> {
+        let pc = self.spans.partition_point(|s| s.end <= pos);
+        (pc < self.spans.len()).then_some(pc)
+    }
 }
 
 /// parse a brainfuck program
 ///
 /// converts a `&str` to a vec of [`BFCommand`]s, while also
 /// removing any invalid chars and checking for correctly nested loops
-pub fn parse(code: &str) -> Option<Vec<BFCommand>> {
-    is_nesting_correct(code).then(|| code.chars().flat_map(lex).collect())
+pub fn parse(code: &str) -> Result<Vec<BFCommand>, ParseError> {
+    parse_with_source_map(code).map(|(code, _)| code)
 }
 
-const TAPE_LEN: usize = 32768;
+/// parse a brainfuck program, keeping track of where each command came from
+///
+/// see [`parse`]
+pub fn parse_with_source_map(code: &str) -> Result<(Vec<BFCommand>, SourceMap), ParseError> {
+    check_nesting(code)?;
+    let (cmds, spans) = code
+        .chars()
+        .enumerate()
+        .filter_map(|(i, c)| Some((lex(c)?, Span { start: i, end: i + 1 })))
+        .unzip();
+    Ok((cmds, SourceMap { spans }))
+}
+
+/// statistics of a finished run
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize)]
+pub struct RunStats {
+    /// clock cycles of the hw interpreter
+    pub cycles: usize,
+    /// instructions executed by the engine, e.g. optimised ops
+    pub steps: usize,
+    /// bytes read from the input
+    pub read: usize,
+    /// bytes written to the output
+    pub written: usize,
+}
 
 /// the digital twin
 #[derive(Debug)]
 pub struct Run {
-    tape: [u8; TAPE_LEN],
+    tape: Tape,
     pub pc: usize,
     pub ic: usize,
     head: usize,
     pub code: Vec<BFCommand>,
+    pub source_map: SourceMap,
     pub jumping: Option<usize>,
     stack: Vec<usize>,
     pub inp: Vec<u8>,
     pub out: Vec<u8>,
     cycles: usize,
+    /// the last step did not execute anything,
+    /// because it waited for input or stopped at a breakpoint
+    pub blocked: bool,
+    breakpoints: Breakpoints,
+    watchpoints: Vec<Watchpoint>,
+    /// set by the last step if the run should be paused
+    pub pause_reason: Option<PauseReason>,
+    history: History,
+    trace: Option<TraceWriter>,
 }
 
 /// a view of the [digital twin](Run)
 ///
 /// determines what the client gets to see
-#[derive(Serialize)]
+#[derive(Debug, Serialize)]
 pub struct RunView {
-    tape: Vec<u8>,
+    tape: Vec<u32>,
     head: usize,
     code: CodeView,
     ic: usize,
@@ -100,20 +230,26 @@ pub struct RunView {
     cycles: usize,
     control_state: String,
     run_state: String,
+    pause_reason: Option<PauseReason>,
+    /// registers of the [edge-accurate model](edge::EdgeRun)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    registers: Option<edge::Registers>,
 }
 
 /// a view of the currently executing code
 ///
 /// part of the [`RunView`]
-#[derive(Serialize)]
+#[derive(Debug, Serialize)]
 pub struct CodeView {
     pc: usize,
     offset: usize,
     fragment: String,
+    /// position of the command at `pc` in the original source
+    source: Option<Span>,
 }
 
 impl CodeView {
-    pub fn new(code: &[impl ToString], pc: usize) -> Self {
+    pub fn new(code: &[impl ToString], source_map: &SourceMap, pc: usize) -> Self {
         let start = Run::VIEW_SIZE.max(pc) - Run::VIEW_SIZE;
         let end = code.len().min(pc + Run::VIEW_SIZE + 1);
         let code_frag: String = code[start..end]
@@ -123,6 +259,7 @@ impl CodeView {
         Self {
             fragment: code_frag,
             offset: start,
+            source: source_map.span(pc),
             pc,
         }
     }
@@ -136,32 +273,169 @@ impl Run {
     /// are sent to the client
     const VIEW_SIZE: usize = 3;
 
-    pub fn new(code: &str, input: &str) -> Option<Self> {
-        Some(Self {
-            tape: [0; TAPE_LEN],
+    pub fn new(code: &str, input: &str, config: MachineConfig) -> Result<Self, ParseError> {
+        let (code, source_map) = parse_with_source_map(code)?;
+        Ok(Self {
+            tape: Tape::new(config),
             pc: 0,
             ic: 0,
             head: 0,
-            code: parse(code)?,
+            code,
+            source_map,
             jumping: None,
             stack: Vec::new(),
             inp: input.as_bytes().to_vec(),
             out: Vec::new(),
             cycles: 0,
+            blocked: false,
+            breakpoints: Breakpoints::default(),
+            watchpoints: Vec::new(),
+            pause_reason: None,
+            history: History::default(),
+            trace: None,
         })
     }
 
-    pub fn view(&self, ctrl_state: &str, run_state: &str) -> RunView {
-        let mut tape_slice = Vec::new();
-        // conversion between isize and usize needed for correct wrapping
-        for i in self.head as isize - Self::VIEW_SIZE as isize
-            ..=self.head as isize + Self::VIEW_SIZE as isize
-        {
-            tape_slice.push(self.tape[i.rem_euclid(TAPE_LEN as isize) as usize]);
+    pub fn snapshot(&self) -> RunSnapshot {
+        RunSnapshot {
+            machine: *self.tape.config(),
+            tape: self.tape.encode(),
+            pc: self.pc,
+            ic: self.ic,
+            head: self.head,
+            jumping: self.jumping,
+            stack: self.stack.clone(),
+            out: self.out.clone(),
+            cycles: self.cycles,
+        }
+    }
+
+    /// resume a run from a [`RunSnapshot`]
+    ///
+    /// the snapshot is checked against the code, so a restored run cannot panic
+    pub fn restore(code: &str, input: &str, state: &RunSnapshot) -> Result<Self, SnapshotError> {
+        let invalid = |reason| Err(SnapshotError::InvalidState(reason));
+        // checked before anything is allocated for the tape
+        if !state.machine.is_valid() {
+            return invalid("tape length must be between 1 and 1048576");
         }
+        let Some(tape) = Tape::decode(state.machine, &state.tape) else {
+            return invalid("tape does not match the machine config");
+        };
+        let mut run = Self::new(code, input, state.machine)?;
+        if state.head >= tape.len() {
+            return invalid("head is outside of the tape");
+        }
+        if state.pc >= run.code.len() {
+            return invalid("pc is outside of the code");
+        }
+        if state.ic > run.inp.len() {
+            return invalid("more input read than available");
+        }
+        if state.jumping == Some(0) {
+            return invalid("jumping depth must not be 0");
+        }
+        if !state.stack.iter().all(|&pc| run.code.get(pc) == Some(&BFCommand::LoopStart)) {
+            return invalid("stack does not point to loops");
+        }
+        run.tape = tape;
+        run.pc = state.pc;
+        run.ic = state.ic;
+        run.head = state.head;
+        run.jumping = state.jumping;
+        run.stack = state.stack.clone();
+        run.out = state.out.clone();
+        run.cycles = state.cycles;
+        Ok(run)
+    }
+
+    /// set breakpoints by char position in the source
+    ///
+    /// a position that is not on a command stops at the next command
+    pub fn set_breakpoints(&mut self, positions: impl IntoIterator<Item = usize>) {
+        let pcs = positions
+            .into_iter()
+            .filter_map(|pos| self.source_map.next_pc(pos));
+        self.breakpoints.set(pcs);
+    }
+
+    pub fn set_watchpoints(&mut self, watchpoints: Vec<Watchpoint>) {
+        self.watchpoints = watchpoints;
+    }
+
+    /// check the watchpoints against the step recorded in `undo`
+    fn check_watchpoints(&mut self, undo: &Undo) {
+        let cmd = self.code[undo.pc];
+        let written = undo.jumping.is_none()
+            && matches!(cmd, BFCommand::Inc | BFCommand::Dec | BFCommand::In);
+        let write = written.then(|| (undo.head, undo.cell, self.tape.get(undo.head)));
+        let head = (undo.head, self.head);
+        if let Some(watchpoint) = self.watchpoints.iter().find(|w| w.triggered(head, write)) {
+            self.pause_reason = Some(PauseReason::Watchpoint {
+                pc: undo.pc,
+                source: self.source_map.span(undo.pc),
+                watchpoint: *watchpoint,
+            });
+        }
+    }
+
+    /// record every executed step to a trace
+    ///
+    /// seeking forward records the replayed steps, seeking back ends the trace
+    /// as it only ever grows. recording stops silently if writing fails,
+    /// [`Run::stop_trace`] reports whether the trace was written completely
+    pub fn record_trace(&mut self, trace: TraceWriter) {
+        self.trace = Some(trace);
+    }
+
+    /// stop recording, flushing the trace
+    pub fn stop_trace(&mut self) -> std::io::Result<()> {
+        match self.trace.take() {
+            Some(mut trace) => trace.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// write the step recorded in `undo` to the trace
+    fn write_trace(&mut self, undo: &Undo) {
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+        let after = self.tape.get(self.head);
+        let io = match self.code[undo.pc] {
+            _ if undo.jumping.is_some() => None,
+            // `,` at the end of the input consumes nothing
+            BFCommand::In if self.ic > undo.ic => Some(IoEvent::In(after)),
+            BFCommand::Out => Some(IoEvent::Out(after as u8)),
+            _ => None,
+        };
+        let before = if self.head == undo.head { undo.cell } else { after };
+        let record = TraceRecord {
+            pc: undo.pc,
+            head: self.head,
+            before,
+            after,
+            jumping: self.jumping,
+            io,
+        };
+        if trace.write(&record).is_err() {
+            self.trace = None;
+        }
+    }
+
+    /// whether the next step will stop at a breakpoint instead of executing
+    pub fn at_breakpoint(&self) -> bool {
+        self.jumping.is_none() && self.breakpoints.should_stop(self.pc, self.cycles)
+    }
+
+    pub fn view(&self, ctrl_state: &str, run_state: &str) -> RunView {
         RunView {
-            tape: tape_slice,
-            code: CodeView::new(&self.code, self.pc),
+            tape: self.tape.window(self.head, Self::VIEW_SIZE),
+            code: CodeView::new(&self.code, &self.source_map, self.pc),
             ic: self.ic,
             head: self.head,
             jumping: self.jumping,
@@ -169,17 +443,58 @@ impl Run {
             cycles: self.cycles,
             control_state: ctrl_state.to_string(),
             run_state: self.jumping.map(|_| "jumping").unwrap_or(run_state).to_string(),
+            pause_reason: self.pause_reason.clone(),
+            registers: None,
+        }
+    }
+
+    /// value the `,` under the program counter would read
+    ///
+    /// applies the [`EofPolicy`](machine::EofPolicy) once the input is exhausted,
+    /// returns `None` if it has to wait for more input
+    pub fn next_input(&self) -> Option<u32> {
+        match self.inp.get(self.ic) {
+            Some(byte) => Some(*byte as u32),
+            None => self.tape.config().eof_value(self.tape.get(self.head)),
         }
     }
 
     /// advance by one step
     ///
-    /// returns true when finished
-    pub fn step(&mut self) -> bool {
+    /// returns true when finished, or an error if the machine model was violated.
+    /// if the step has to wait for input or stops at a breakpoint, nothing is executed
+    /// and [`Run::blocked`] is set, so the caller must not advance the program counter
+    pub fn step(&mut self) -> Result<bool, MachineError> {
         // this should not happen
         if self.pc >= self.code.len() {
-            return true;
+            return Ok(true);
+        }
+        self.pause_reason = None;
+        if self.at_breakpoint() {
+            self.breakpoints.hit(self.cycles);
+            self.pause_reason = Some(PauseReason::Breakpoint {
+                pc: self.pc,
+                source: self.source_map.span(self.pc),
+            });
+            self.blocked = true;
+            return Ok(false);
         }
+        self.blocked = self.jumping.is_none()
+            && self.code[self.pc] == BFCommand::In
+            && self.next_input().is_none();
+        if self.blocked {
+            return Ok(false);
+        }
+        let undo = Undo {
+            pc: self.pc,
+            head: self.head,
+            cell: self.tape.get(self.head),
+            jumping: self.jumping,
+            stack_len: self.stack.len(),
+            stack_top: self.stack.last().copied(),
+            ic: self.ic,
+            out_len: self.out.len(),
+        };
         self.cycles += 1;
         if let Some(depth) = &mut self.jumping {
             match self.code[self.pc] {
@@ -192,21 +507,19 @@ impl Run {
             }
         } else {
             match self.code[self.pc] {
-                BFCommand::Inc => self.tape[self.head] = self.tape[self.head].wrapping_add(1),
-                BFCommand::Dec => self.tape[self.head] = self.tape[self.head].wrapping_sub(1),
-                BFCommand::Left => self.head = (self.head.wrapping_sub(1)) % TAPE_LEN,
-                BFCommand::Right => self.head = (self.head.wrapping_add(1)) % TAPE_LEN,
+                BFCommand::Inc => self.tape.add(self.head, 1)?,
+                BFCommand::Dec => self.tape.add(self.head, -1)?,
+                BFCommand::Left => self.head = self.tape.offset(self.head, -1)?,
+                BFCommand::Right => self.head = self.tape.offset(self.head, 1)?,
                 BFCommand::In => {
-                    if self.ic < self.inp.len() {
-                        self.tape[self.head] = self.inp[self.ic];
-                        self.ic += 1;
-                    } else {
-                        self.tape[self.head] = 0;
-                    }
+                    // unwrap: checked for blocking above
+                    self.tape.set(self.head, self.next_input().unwrap());
+                    self.ic = self.inp.len().min(self.ic + 1);
                 }
-                BFCommand::Out => self.out.push(self.tape[self.head]),
+                // wider cells are truncated to their lowest byte
+                BFCommand::Out => self.out.push(self.tape.get(self.head) as u8),
                 BFCommand::LoopStart => {
-                    if self.tape[self.head] == 0 {
+                    if self.tape.get(self.head) == 0 {
                         self.jumping = Some(1);
                     } else {
                         self.stack.push(self.pc);
@@ -216,7 +529,7 @@ impl Run {
                     // probably correct behaviour for incorrect code
                     // should actually never happen
                     let back_addr = self.stack.last().unwrap_or(&0);
-                    if self.tape[self.head] != 0 {
+                    if self.tape.get(self.head) != 0 {
                         self.pc = *back_addr;
                     } else {
                         self.stack.pop();
@@ -224,7 +537,142 @@ impl Run {
                 }
             }
         }
-        self.pc + 1 == self.code.len()
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(&undo);
+        }
+        self.write_trace(&undo);
+        self.history.push(undo);
+        Ok(self.pc + 1 == self.code.len())
+    }
+
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    pub fn tape(&self) -> &Tape {
+        &self.tape
+    }
+
+    pub fn head(&self) -> usize {
+        self.head
+    }
+
+    /// run to the end, reading input from `io` whenever `,` runs out of it
+    /// and writing the output as it is produced
+    ///
+    /// breakpoints are passed without stopping
+    pub fn run_io(&mut self, io: &mut impl Io) -> Result<RunStats, RunError> {
+        let mut eof = false;
+        loop {
+            if !eof
+                && self.jumping.is_none()
+                && self.code.get(self.pc) == Some(&BFCommand::In)
+                && self.ic >= self.inp.len()
+            {
+                match io.read()? {
+                    Some(byte) => self.inp.push(byte),
+                    None => eof = true,
+                }
+            }
+            let out_len = self.out.len();
+            let finished = self.step()?;
+            if let Some(&byte) = self.out.get(out_len) {
+                io.write(byte)?;
+            }
+            if finished {
+                break;
+            }
+            if self.blocked && self.pause_reason.is_none() {
+                // waiting for input that never arrives
+                return Err(MachineError::InputExhausted.into());
+            }
+            if !self.blocked {
+                self.pc += 1;
+            }
+        }
+        io.flush()?;
+        Ok(RunStats {
+            cycles: self.cycles,
+            steps: self.cycles,
+            read: self.ic,
+            written: self.out.len(),
+        })
+    }
+
+    /// undo up to `n` steps
+    ///
+    /// afterwards, the program counter points to the next command to execute.
+    /// returns how many steps were undone
+    pub fn step_back(&mut self, n: usize) -> usize {
+        let cycles = self.cycles;
+        // replaying cannot fail, as the run got this far before
+        let _ = self.seek(cycles.saturating_sub(n));
+        cycles - self.cycles
+    }
+
+    /// move to the state after `cycle` cycles, ignoring breakpoints and watchpoints
+    ///
+    /// recent steps are undone using the history, anything older is replayed from the start.
+    /// seeking forward stops early when the run is finished or blocked by input.
+    /// returns true when finished
+    pub fn seek(&mut self, cycle: usize) -> Result<bool, MachineError> {
+        self.blocked = false;
+        self.pause_reason = None;
+        if cycle < self.cycles {
+            // the undone steps are already in the trace
+            let _ = self.stop_trace();
+            if self.cycles - cycle > self.history.len() {
+                self.restart();
+            }
+            while self.cycles > cycle {
+                // unwrap: enough history checked above
+                let undo = self.history.pop().unwrap();
+                self.undo(undo);
+            }
+        }
+        let breakpoints = std::mem::take(&mut self.breakpoints);
+        let watchpoints = std::mem::take(&mut self.watchpoints);
+        let mut res = Ok(false);
+        while self.cycles < cycle {
+            res = self.step();
+            match res {
+                Ok(false) if !self.blocked => self.pc += 1,
+                _ => break,
+            }
+        }
+        self.breakpoints = breakpoints;
+        self.watchpoints = watchpoints;
+        res
+    }
+
+    fn undo(&mut self, undo: Undo) {
+        self.pc = undo.pc;
+        self.head = undo.head;
+        self.tape.set(undo.head, undo.cell);
+        self.jumping = undo.jumping;
+        if self.stack.len() > undo.stack_len {
+            self.stack.truncate(undo.stack_len);
+        } else if self.stack.len() < undo.stack_len {
+            // unwrap: the step popped this address
+            self.stack.push(undo.stack_top.unwrap());
+        }
+        self.ic = undo.ic;
+        self.out.truncate(undo.out_len);
+        self.cycles -= 1;
+    }
+
+    /// reset everything but code, input and breakpoints
+    fn restart(&mut self) {
+        self.tape = Tape::new(*self.tape.config());
+        self.pc = 0;
+        self.ic = 0;
+        self.head = 0;
+        self.jumping = None;
+        self.stack.clear();
+        self.out.clear();
+        self.cycles = 0;
+        self.blocked = false;
+        self.history.clear();
     }
 }
 
@@ -235,12 +683,370 @@ mod test {
     #[test]
     fn test_hello_world() {
         let code = "+++++++++++[>++++++>+++++++++>++++++++>++++>+++>+<<<<<<-]>++++++.>++.+++++++..+++.>>.>-.<<-.<.+++.------.--------.>>>+.>-.";
-        let mut run = Run::new(code, "").expect("code should be valid");
-        while !run.step() {}
+        let mut run = Run::new(code, "", MachineConfig::default()).expect("code should be valid");
+        while !run.step().expect("default machine should not fail") {
+            run.pc += 1;
+        }
         assert_eq!(
             "Hello, World!\n".to_string(),
             String::from_utf8(run.out).unwrap()
         );
         assert_eq!(run.cycles, 572);
     }
+
+    #[test]
+    fn test_parse_error() {
+        assert_eq!(check_nesting("+[-[>]<]."), Ok(()));
+        let err = parse("[+\n]]\n x[[-]").unwrap_err();
+        let pos: Vec<_> = err
+            .unmatched
+            .iter()
+            .map(|b| (b.bracket, b.offset, b.line, b.column))
+            .collect();
+        assert_eq!(pos, vec![(']', 4, 2, 2), ('[', 8, 3, 3)]);
+    }
+
+    #[test]
+    fn test_source_map() {
+        let (code, map) = parse_with_source_map("a+ [ö-]\n.").unwrap();
+        assert_eq!(code.len(), 5);
+        assert_eq!(map.span(0), Some(Span { start: 1, end: 2 }));
+        assert_eq!(map.span(2), Some(Span { start: 5, end: 6 }));
+        assert_eq!(map.span(4), Some(Span { start: 8, end: 9 }));
+        assert_eq!(map.span(5), None);
+        assert_eq!(map.next_pc(5), Some(2));
+        assert_eq!(map.next_pc(4), Some(2));
+        assert_eq!(map.next_pc(9), None);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let code = "+[-#]>\n+";
+        let mut run = Run::new(code, "", MachineConfig::default()).unwrap();
+        run.set_breakpoints(debug::hash_positions(code).chain([6]));
+        let mut stops = Vec::new();
+        loop {
+            let finished = run.step().unwrap();
+            if let Some(PauseReason::Breakpoint { pc, .. }) = run.pause_reason {
+                stops.push((pc, run.cycles));
+            }
+            if finished {
+                break;
+            }
+            if !run.blocked {
+                run.pc += 1;
+            }
+        }
+        // `#` stops before `]`, position 6 is the newline before the last `+`
+        assert_eq!(stops, vec![(3, 3), (5, 5)]);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let code = "++[->+>+<<]>>[-]";
+        let mut run = Run::new(code, "", MachineConfig::default()).unwrap();
+        run.set_watchpoints(vec![
+            Watchpoint::Value { cell: 1, value: 2 },
+            Watchpoint::Write { cell: 2 },
+            Watchpoint::Leave { start: 0, end: 2 },
+        ]);
+        let mut hits = Vec::new();
+        loop {
+            let finished = run.step().unwrap();
+            if let Some(PauseReason::Watchpoint { pc, watchpoint, .. }) = run.pause_reason {
+                hits.push((pc, watchpoint));
+            }
+            if finished {
+                break;
+            }
+            run.pc += 1;
+        }
+        let write = Watchpoint::Write { cell: 2 };
+        let leave = Watchpoint::Leave { start: 0, end: 2 };
+        assert_eq!(
+            hits,
+            vec![
+                (6, leave),
+                (7, write),
+                (5, Watchpoint::Value { cell: 1, value: 2 }),
+                (6, leave),
+                (7, write),
+                (12, leave),
+                (14, write),
+                (14, write),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_step_back() {
+        let code = "+++[->++<]>.<,";
+        let mut run = Run::new(code, "a", MachineConfig::default()).unwrap();
+        let mut states = Vec::new();
+        loop {
+            states.push(format!("{:?}", run.view("", "")));
+            if run.step().unwrap() {
+                break;
+            }
+            run.pc += 1;
+        }
+        let end = run.cycles;
+        assert_eq!(run.step_back(3), 3);
+        assert_eq!(format!("{:?}", run.view("", "")), states[end - 3]);
+        assert_eq!(run.seek(end - 1), Ok(false));
+        assert_eq!(format!("{:?}", run.view("", "")), states[end - 1]);
+        // replay from the start once the history is gone
+        run.history.clear();
+        run.seek(5).unwrap();
+        assert_eq!(format!("{:?}", run.view("", "")), states[5]);
+        assert_eq!(run.step_back(10), 5);
+        assert_eq!(format!("{:?}", run.view("", "")), states[0]);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let code = "++[->+++<]>[>+<-]+,.";
+        let mut run = Run::new(code, "x", MachineConfig::default()).unwrap();
+        for _ in 0..20 {
+            run.step().unwrap();
+            run.pc += 1;
+        }
+        let snapshot = snapshot::Snapshot::new(code.into(), "x".into(), String::new(), Some(run.snapshot()));
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let restored: snapshot::Snapshot = serde_json::from_str(&json).unwrap();
+        let mut restored = restored.restore_run().unwrap().unwrap();
+        assert_eq!(restored.snapshot(), run.snapshot());
+        while !run.step().unwrap() {
+            run.pc += 1;
+        }
+        while !restored.step().unwrap() {
+            restored.pc += 1;
+        }
+        assert_eq!(restored.snapshot(), run.snapshot());
+
+        let mut broken = snapshot.clone();
+        broken.version = 0;
+        assert_eq!(broken.restore_run().unwrap_err(), SnapshotError::UnsupportedVersion(0));
+        let mut broken = snapshot.clone();
+        broken.run.as_mut().unwrap().stack = vec![0];
+        assert!(matches!(broken.restore_run(), Err(SnapshotError::InvalidState(_))));
+        // runs that would allocate far more than the tape, or overflow the length
+        let mut broken = snapshot.clone();
+        broken.run.as_mut().unwrap().tape = vec![(0, usize::MAX), (0, 2)];
+        assert!(matches!(broken.restore_run(), Err(SnapshotError::InvalidState(_))));
+        let mut broken = snapshot;
+        let state = broken.run.as_mut().unwrap();
+        state.machine.tape_len = usize::MAX;
+        state.tape = vec![(0, usize::MAX)];
+        assert!(matches!(broken.restore_run(), Err(SnapshotError::InvalidState(_))));
+    }
+
+    #[test]
+    fn test_trace() {
+        // reads past the end of the input,
+        // then ends left of cell 0, which wraps to the end of the tape
+        let code = "a,[>+++[->++<]>.<<-],<->#";
+        let path = std::env::temp_dir().join(format!("bf_itp_trace_{}", std::process::id()));
+        let config = MachineConfig::default();
+        let file = std::fs::File::create(&path).unwrap();
+        let mut run = Run::new(code, "\x03", config).unwrap();
+        run.record_trace(TraceWriter::new(std::io::BufWriter::new(file), code, &config).unwrap());
+        // steps replayed by seeking forward are recorded as well
+        run.seek(10).unwrap();
+        while !run.step().unwrap() {
+            run.pc += 1;
+        }
+        run.stop_trace().unwrap();
+
+        let file = std::fs::File::open(&path).unwrap();
+        let records: Vec<_> = trace::TraceReader::new(file)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), run.cycles());
+        assert_eq!(records[0].io, Some(IoEvent::In(3)));
+        let inputs = records.iter().filter(|record| matches!(record.io, Some(IoEvent::In(_))));
+        assert_eq!(inputs.count(), 1);
+
+        let file = std::fs::File::open(&path).unwrap();
+        let mut replay = trace::TraceReplay::new(std::io::BufReader::new(file)).unwrap();
+        assert_eq!(replay.source(), code);
+        while replay.step().unwrap() {}
+        assert_eq!(replay.out, run.out);
+        let (expected, actual) = (run.view("", ""), replay.view("", ""));
+        assert_eq!(actual.tape, expected.tape);
+        assert_eq!(actual.head, expected.head);
+        assert_eq!(actual.stack, expected.stack);
+        assert_eq!(actual.cycles, expected.cycles);
+        assert_eq!(actual.ic, expected.ic);
+        assert_eq!(actual.tape[Run::VIEW_SIZE - 1], 255);
+
+        // seeking back ends the trace, which keeps the steps up to then
+        let file = std::fs::File::create(&path).unwrap();
+        let mut run = Run::new(code, "\x03", config).unwrap();
+        run.record_trace(TraceWriter::new(file, code, &config).unwrap());
+        run.seek(20).unwrap();
+        run.step_back(5);
+        assert!(!run.is_tracing());
+        run.seek(30).unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        assert_eq!(trace::TraceReader::new(file).unwrap().count(), 20);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut header = b"BFTR\x01".to_vec();
+        header.extend([0xff, 0xff, 0xff, 0xff, 0x0f]);
+        assert!(trace::TraceReader::new(&header[..]).is_err());
+    }
+
+    #[test]
+    fn test_edge_run() {
+        let code = "+++++++++++[>++++++>+++++++++>++++++++>++++>+++>+<<<<<<-]>++++++.>++.+++++++..+++.>>.>-.<<-.<.+++.------.--------.>>>+.>-.";
+        let mut run = edge::EdgeRun::new(code, "").unwrap();
+        while !run.step() {}
+        assert_eq!(run.out, b"Hello, World!\n");
+        // startup sweep and enabling edge, then one cycle per command like the digital twin,
+        // plus one per output (14) and per `[` executed again after jumping back (10)
+        assert_eq!(run.cycles(), machine::TAPE_LEN + 1 + 572 + 14 + 10);
+
+        let mut run = edge::EdgeRun::new(",[.[-],]", "ab").unwrap();
+        for _ in 0..machine::TAPE_LEN {
+            run.tick();
+        }
+        assert_eq!(run.state(), edge::HwState::Startup);
+        run.tick();
+        assert_eq!(run.state(), edge::HwState::Input);
+        while !run.waiting_input() {
+            run.step();
+        }
+        assert_eq!(run.out, b"ab");
+        run.inp.push(0);
+        while !run.step() {}
+        assert_eq!(run.ic, 3);
+    }
+
+    fn run_to_end(code: &str, config: MachineConfig) -> Result<Run, MachineError> {
+        let mut run = Run::new(code, "", config).expect("code should be valid");
+        while !run.step()? {
+            run.pc += 1;
+        }
+        Ok(run)
+    }
+
+    #[test]
+    fn test_machine_config() {
+        use machine::{BoundaryPolicy, CellWidth, OverflowPolicy};
+
+        let wide = MachineConfig {
+            cell_width: CellWidth::U16,
+            ..Default::default()
+        };
+        let run = run_to_end("-", wide).unwrap();
+        assert_eq!(run.tape.get(0), u16::MAX as u32);
+
+        let saturate = MachineConfig {
+            overflow: OverflowPolicy::Saturate,
+            ..Default::default()
+        };
+        let run = run_to_end("--+", saturate).unwrap();
+        assert_eq!(run.tape.get(0), 1);
+
+        let error = MachineConfig {
+            overflow: OverflowPolicy::Error,
+            ..Default::default()
+        };
+        assert_eq!(
+            run_to_end(">-", error).unwrap_err(),
+            MachineError::CellOverflow { cell: 1 }
+        );
+
+        let bounded = MachineConfig {
+            tape_len: 2,
+            boundary: BoundaryPolicy::Error,
+            ..Default::default()
+        };
+        assert_eq!(
+            run_to_end(">>", bounded).unwrap_err(),
+            MachineError::PointerOutOfBounds { head: 2 }
+        );
+
+        let grow = MachineConfig {
+            tape_len: 2,
+            boundary: BoundaryPolicy::Grow,
+            ..Default::default()
+        };
+        let run = run_to_end(">>>+", grow).unwrap();
+        assert_eq!((run.head, run.tape.len()), (3, 4));
+        let mut tape = Tape::new(grow);
+        assert_eq!(
+            tape.offset(0, machine::MAX_TAPE_LEN as isize),
+            Err(MachineError::PointerOutOfBounds { head: 2 })
+        );
+        assert!(!MachineConfig {
+            tape_len: machine::MAX_TAPE_LEN + 1,
+            ..Default::default()
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn test_eof_policy() {
+        use machine::EofPolicy;
+
+        for (eof, exp) in [
+            (EofPolicy::Zero, 0),
+            (EofPolicy::Max, 255),
+            (EofPolicy::Unchanged, 7),
+        ] {
+            let config = MachineConfig {
+                eof,
+                ..Default::default()
+            };
+            let run = run_to_end("+++++++,", config).unwrap();
+            assert_eq!(run.tape.get(0), exp);
+            let prog = optimized::Prog::new("+++++++,.", config).unwrap();
+            assert_eq!(prog.run_bytes(b"").unwrap().1, vec![exp as u8]);
+        }
+
+        let config = MachineConfig {
+            eof: EofPolicy::Block,
+            ..Default::default()
+        };
+        let mut run = Run::new(",.", "", config).unwrap();
+        assert!(!run.step().unwrap());
+        assert!(run.blocked);
+        run.inp.push(b'a');
+        assert!(!run.step().unwrap());
+        assert!(!run.blocked);
+        let prog = optimized::Prog::new(",.", config).unwrap();
+        assert_eq!(prog.run_bytes(b""), Err(MachineError::InputExhausted));
+    }
+
+    #[test]
+    fn test_run_io() {
+        use io::StreamIo;
+
+        // reads more input only after writing the output so far
+        let code = ",[.,]";
+        let mut io = StreamIo::new(&b"cat"[..], Vec::new());
+        let mut run = Run::new(code, "", MachineConfig::default()).unwrap();
+        let stats = run.run_io(&mut io).unwrap();
+        assert_eq!(io.into_inner().1, b"cat");
+        assert_eq!((stats.read, stats.written), (3, 3));
+
+        let prog = optimized::Prog::new(code, MachineConfig::default()).unwrap();
+        let mut io = StreamIo::new(&b"cat"[..], Vec::new());
+        assert_eq!(prog.run(&mut io).unwrap().cycles, stats.cycles);
+        assert_eq!(io.into_inner().1, b"cat");
+
+        let config = MachineConfig {
+            eof: machine::EofPolicy::Block,
+            ..Default::default()
+        };
+        let mut run = Run::new(code, "", config).unwrap();
+        let res = run.run_io(&mut StreamIo::new(&b"a"[..], Vec::new()));
+        assert!(matches!(
+            res,
+            Err(RunError::Machine(MachineError::InputExhausted))
+        ));
+    }
 }
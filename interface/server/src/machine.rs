@@ -0,0 +1,297 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// tape length of the physical interpreter (32 KiB SRAM)
+pub const TAPE_LEN: usize = 32768;
+
+/// longest tape of the twin, also the limit of a growing tape
+pub const MAX_TAPE_LEN: usize = 1 << 20;
+
+/// width of a single tape cell
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CellWidth {
+    U8,
+    U16,
+    U32,
+}
+
+impl CellWidth {
+    /// largest value a cell can hold
+    pub fn max(self) -> u32 {
+        match self {
+            CellWidth::U8 => u8::MAX as u32,
+            CellWidth::U16 => u16::MAX as u32,
+            CellWidth::U32 => u32::MAX,
+        }
+    }
+}
+
+/// what happens when a cell is incremented above its max or decremented below 0
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    Wrap,
+    Saturate,
+    Error,
+}
+
+/// what happens when the data pointer leaves the tape
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BoundaryPolicy {
+    /// wrap around to the other end of the tape
+    Wrap,
+    /// abort the run
+    Error,
+    /// extend the tape to the right, moving left of cell 0 is still an error
+    Grow,
+}
+
+/// what `,` does once the input is exhausted
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EofPolicy {
+    /// write 0
+    #[default]
+    Zero,
+    /// write the largest cell value (255 / -1 for 8 bit cells)
+    Max,
+    /// leave the cell unchanged
+    Unchanged,
+    /// wait until more input arrives
+    Block,
+}
+
+/// the machine model used by the [digital twin](crate::Run) and the [optimised runner](crate::optimized::Prog)
+///
+/// the default matches the physical interpreter
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MachineConfig {
+    pub tape_len: usize,
+    pub cell_width: CellWidth,
+    pub overflow: OverflowPolicy,
+    pub boundary: BoundaryPolicy,
+    pub eof: EofPolicy,
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            tape_len: TAPE_LEN,
+            cell_width: CellWidth::U8,
+            overflow: OverflowPolicy::Wrap,
+            boundary: BoundaryPolicy::Wrap,
+            eof: EofPolicy::Zero,
+        }
+    }
+}
+
+impl MachineConfig {
+    /// the tape has between 1 and [`MAX_TAPE_LEN`] cells
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_TAPE_LEN).contains(&self.tape_len)
+    }
+
+    /// value `,` writes once the input is exhausted
+    ///
+    /// `current` is the value of the cell under the head.
+    /// returns `None` if the machine has to wait for more input
+    pub fn eof_value(&self, current: u32) -> Option<u32> {
+        match self.eof {
+            EofPolicy::Zero => Some(0),
+            EofPolicy::Max => Some(self.cell_width.max()),
+            EofPolicy::Unchanged => Some(current),
+            EofPolicy::Block => None,
+        }
+    }
+}
+
+/// errors caused by the machine model, aborting a run
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MachineError {
+    /// a cell left its value range with [`OverflowPolicy::Error`]
+    CellOverflow { cell: usize },
+    /// the data pointer left the tape
+    PointerOutOfBounds { head: isize },
+    /// `,` blocks with [`EofPolicy::Block`], but no more input will arrive
+    InputExhausted,
+}
+
+impl Display for MachineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MachineError::CellOverflow { cell } => write!(f, "cell {cell} overflowed"),
+            MachineError::PointerOutOfBounds { head } => {
+                write!(f, "data pointer left the tape at {head}")
+            }
+            MachineError::InputExhausted => write!(f, "waiting for input that never arrives"),
+        }
+    }
+}
+
+/// the tape of a brainfuck machine
+///
+/// applies the arithmetic and boundary rules of its [`MachineConfig`]
+#[derive(Debug, Clone)]
+pub struct Tape {
+    cells: Vec<u32>,
+    config: MachineConfig,
+}
+
+impl Tape {
+    pub fn new(config: MachineConfig) -> Self {
+        Self {
+            cells: vec![0; config.tape_len],
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &MachineConfig {
+        &self.config
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub fn get(&self, idx: usize) -> u32 {
+        self.cells[idx]
+    }
+
+    /// all cells, for runners applying the rules of the config themselves
+    pub(crate) fn cells_mut(&mut self) -> &mut [u32] {
+        &mut self.cells
+    }
+
+    /// write a value, truncating it to the cell width
+    pub fn set(&mut self, idx: usize, value: u32) {
+        self.cells[idx] = value & self.config.cell_width.max();
+    }
+
+    /// add a (possibly negative) amount to a cell
+    pub fn add(&mut self, idx: usize, delta: i64) -> Result<(), MachineError> {
+        let max = self.config.cell_width.max() as i64;
+        let value = self.cells[idx] as i64 + delta;
+        let value = match self.config.overflow {
+            OverflowPolicy::Wrap => value.rem_euclid(max + 1),
+            OverflowPolicy::Saturate => value.clamp(0, max),
+            OverflowPolicy::Error if !(0..=max).contains(&value) => {
+                return Err(MachineError::CellOverflow { cell: idx });
+            }
+            OverflowPolicy::Error => value,
+        };
+        self.cells[idx] = value as u32;
+        Ok(())
+    }
+
+    /// address `by` cells away from `head`
+    ///
+    /// with [`BoundaryPolicy::Grow`] the tape is extended up to [`MAX_TAPE_LEN`] cells.
+    /// the twin moves one cell at a time, so an error reports the first cell past the end
+    pub fn offset(&mut self, head: usize, by: isize) -> Result<usize, MachineError> {
+        let target = head as isize + by;
+        let len = self.cells.len() as isize;
+        match self.config.boundary {
+            BoundaryPolicy::Wrap => Ok(target.rem_euclid(len) as usize),
+            _ if (0..len).contains(&target) => Ok(target as usize),
+            BoundaryPolicy::Grow if target >= len && (target as usize) < MAX_TAPE_LEN => {
+                self.cells.resize(target as usize + 1, 0);
+                Ok(target as usize)
+            }
+            _ => Err(MachineError::PointerOutOfBounds {
+                head: target.clamp(-1, len),
+            }),
+        }
+    }
+
+    /// first zero cell reached from `head` in steps of `stride`, with the number of steps
+    ///
+    /// every step follows the rules of [`Tape::offset`]; on a wrapping tape without
+    /// a reachable zero cell, the loop it replaces never ends, so the scan gives up
+    /// on a nonzero cell once it took `max_steps` steps
+    pub fn scan(
+        &mut self,
+        head: usize,
+        stride: isize,
+        max_steps: usize,
+    ) -> Result<(usize, usize), MachineError> {
+        let (mut idx, mut steps) = (head, 0);
+        // fast path for `[>]` and `[<]`, searching up to the end of the tape at once
+        if stride == 1 {
+            match self.cells[head..].iter().position(|&c| c == 0) {
+                Some(n) => return Ok((head + n, n)),
+                None => (idx, steps) = (self.cells.len() - 1, self.cells.len() - 1 - head),
+            }
+        } else if stride == -1 {
+            match self.cells[..=head].iter().rposition(|&c| c == 0) {
+                Some(i) => return Ok((i, head - i)),
+                None => (idx, steps) = (0, head),
+            }
+        }
+        while self.cells[idx] != 0 && steps < max_steps {
+            idx = self.offset(idx, stride)?;
+            steps += 1;
+        }
+        Ok((idx, steps))
+    }
+
+    /// run-length encode the cells as `(value, count)` pairs
+    pub fn encode(&self) -> Vec<(u32, usize)> {
+        let mut runs: Vec<(u32, usize)> = Vec::new();
+        for &cell in &self.cells {
+            match runs.last_mut() {
+                Some((value, count)) if *value == cell => *count += 1,
+                _ => runs.push((cell, 1)),
+            }
+        }
+        runs
+    }
+
+    /// restore a tape from [`Tape::encode`]
+    ///
+    /// returns `None` if the cells do not fit the config
+    pub fn decode(config: MachineConfig, runs: &[(u32, usize)]) -> Option<Self> {
+        // check the length before allocating, the runs may come from an upload
+        let len = runs
+            .iter()
+            .try_fold(0_usize, |len, &(_, count)| len.checked_add(count))?;
+        let len_valid = match config.boundary {
+            BoundaryPolicy::Grow => (config.tape_len..=MAX_TAPE_LEN).contains(&len),
+            _ => len == config.tape_len,
+        };
+        if !config.is_valid() || !len_valid {
+            return None;
+        }
+        let mut cells = Vec::with_capacity(len);
+        for &(value, count) in runs {
+            if value > config.cell_width.max() {
+                return None;
+            }
+            cells.resize(cells.len() + count, value);
+        }
+        Some(Self { cells, config })
+    }
+
+    /// cells around `head`, used for views
+    ///
+    /// cells outside of a non-wrapping tape are shown as 0
+    pub fn window(&self, head: usize, radius: usize) -> Vec<u32> {
+        let len = self.cells.len() as isize;
+        // conversion between isize and usize needed for correct wrapping
+        (head as isize - radius as isize..=head as isize + radius as isize)
+            .map(|i| match self.config.boundary {
+                BoundaryPolicy::Wrap => self.cells[i.rem_euclid(len) as usize],
+                _ if (0..len).contains(&i) => self.cells[i as usize],
+                _ => 0,
+            })
+            .collect()
+    }
+}
@@ -1,13 +1,25 @@
 use std::{
-    io::Cursor,
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Cursor},
+    path::{Path, PathBuf},
     sync::{
         mpsc::{self, Sender},
         OnceLock, RwLock,
     },
-    time::Instant,
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
-use bf_itp::{CodeView, Run};
+use bf_itp::{
+    debug::Watchpoint,
+    edge::EdgeRun,
+    leaderboard::{Entry, Leaderboard, Page},
+    machine::{EofPolicy, MachineConfig},
+    skill::{self, Challenge, Score, ScoringProfile, Task},
+    snapshot::{Snapshot, SnapshotError},
+    trace::{TraceReplay, TraceWriter},
+    CodeView, ParseError, Run,
+};
 use rocket::{
     fs::{relative, FileServer},
     http::{ContentType, Status},
@@ -46,11 +58,33 @@ async fn start_rocket() {
 pub enum ItpState {
     Idle,
     Startup,
-    Running { run: Run, paused: bool },
+    Running { run: Box<Run>, paused: bool },
     // usize: input counter
     Uncontrolled(usize),
+    /// running the edge-accurate model of the hw interpreter
+    Edge { run: Box<EdgeRun>, paused: bool },
+    /// playing back a recorded trace instead of executing
+    Replay {
+        replay: Box<TraceReplay>,
+        paused: bool,
+    },
 }
 
+/// directory recorded traces are stored in
+const TRACE_DIR: &str = "traces";
+
+/// most challenges kept at once, the oldest ones are dropped first
+const MAX_CHALLENGES: usize = 1000;
+
+/// log of all scored submissions, kept across restarts
+const LEADERBOARD_FILE: &str = "leaderboard.jsonl";
+
+/// test case tasks offered besides printing a target
+const TASKS_FILE: &str = "tasks.json";
+
+/// longest name on the leaderboard, longer ones are cut off
+const MAX_NAME_LEN: usize = 32;
+
 static GLOBAL_STATE: OnceLock<Global> = OnceLock::new();
 
 #[derive(Debug)]
@@ -62,6 +96,12 @@ pub struct ChangeTimes {
     state: RwLock<Instant>,
 }
 
+impl Default for ChangeTimes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ChangeTimes {
     pub fn new() -> Self {
         Self {
@@ -81,11 +121,26 @@ pub struct Global {
     // frequency = 10^(3 * log10(speed)) = speed^3
     // interval = 1 / frequency
     speed: RwLock<u8>,
+    machine: RwLock<MachineConfig>,
     full_code: RwLock<String>,
     input: RwLock<String>,
     output: RwLock<String>,
     state: RwLock<ItpState>,
     hw_state: RwLock<HWState>,
+    /// breakpoints as char positions in `full_code`
+    breakpoints: RwLock<BTreeSet<usize>>,
+    /// whether `#` in the code acts as a breakpoint
+    hash_breakpoints: RwLock<bool>,
+    watchpoints: RwLock<Vec<Watchpoint>>,
+    /// whether new runs record a trace to [`TRACE_DIR`]
+    trace: RwLock<bool>,
+    /// whether new runs use the edge-accurate model
+    edge_accurate: RwLock<bool>,
+    /// skill challenges by id
+    challenges: RwLock<BTreeMap<u64, Challenge>>,
+    leaderboard: Leaderboard,
+    /// tasks from [`TASKS_FILE`]
+    tasks: Vec<Task>,
     last_change: ChangeTimes,
 }
 
@@ -94,11 +149,20 @@ impl Global {
         Self {
             hw,
             speed: RwLock::new(100),
+            machine: RwLock::new(MachineConfig::default()),
             full_code: RwLock::new(String::new()),
             input: RwLock::new(String::new()),
             output: RwLock::new(String::new()),
             state: RwLock::new(ItpState::Idle),
             hw_state: RwLock::new(HWState::Regular),
+            breakpoints: RwLock::new(BTreeSet::new()),
+            hash_breakpoints: RwLock::new(false),
+            watchpoints: RwLock::new(Vec::new()),
+            trace: RwLock::new(false),
+            edge_accurate: RwLock::new(false),
+            challenges: RwLock::new(BTreeMap::new()),
+            leaderboard: Leaderboard::open(LEADERBOARD_FILE).expect("cannot read the leaderboard"),
+            tasks: Self::load_tasks(),
             last_change: ChangeTimes::new(),
         }
     }
@@ -121,7 +185,12 @@ impl Global {
     }
 
     fn set_state(&self, state: ItpState) {
-        *self.state.write().unwrap() = state;
+        let old = std::mem::replace(&mut *self.state.write().unwrap(), state);
+        if let ItpState::Running { mut run, .. } = old {
+            if let Err(err) = run.stop_trace() {
+                eprintln!("cannot record trace: {err}");
+            }
+        }
         *self.last_change.state.write().unwrap() = Instant::now();
     }
 
@@ -132,35 +201,33 @@ impl Global {
 
     pub fn change_input(&self, inp: String) -> BFRes {
         let read_state = self.state.read().unwrap();
-        match *read_state {
-            ItpState::Running { ref run, .. } => {
-                let curr = self.input.read().unwrap();
-                if inp.len() >= run.ic && curr[..run.ic] == inp[..run.ic] {
-                    drop(read_state);
-                    drop(curr);
-                    let mut write_state = self.state.write().unwrap();
-                    let ItpState::Running { ref mut run, .. } = *write_state else {
-                        unreachable!("was running just a second ago");
-                    };
-                    run.inp = inp.as_bytes().to_vec();
-                    drop(write_state);
-                    self.set_input(inp);
-                    Ok(())
-                } else {
-                    Err(BFError::InputChanged)
-                }
-            }
+        let ic = match *read_state {
+            ItpState::Running { ref run, .. } => run.ic,
+            ItpState::Edge { ref run, .. } => run.ic,
             _ => {
+                drop(read_state);
                 self.set_input(inp);
-                Ok(())
+                return Ok(());
+            }
+        };
+        let curr = self.input.read().unwrap();
+        if inp.len() >= ic && curr[..ic] == inp[..ic] {
+            drop(read_state);
+            drop(curr);
+            match *self.state.write().unwrap() {
+                ItpState::Running { ref mut run, .. } => run.inp = inp.as_bytes().to_vec(),
+                ItpState::Edge { ref mut run, .. } => run.inp = inp.as_bytes().to_vec(),
+                _ => unreachable!("was running just a second ago"),
             }
+            self.set_input(inp);
+            Ok(())
+        } else {
+            Err(BFError::InputChanged)
         }
     }
 
     pub fn change_code(&self, code: String) -> BFRes {
-        if !bf_itp::is_nesting_correct(&code) {
-            return Err(BFError::InvalidNesting);
-        }
+        bf_itp::check_nesting(&code).map_err(BFError::InvalidNesting)?;
         match *self.state.read().unwrap() {
             ItpState::Idle => {
                 self.set_code(code);
@@ -169,6 +236,7 @@ impl Global {
             }
             ItpState::Startup => Err(BFError::CodeChanged),
             ItpState::Running { .. } => Err(BFError::CodeChanged),
+            ItpState::Edge { .. } | ItpState::Replay { .. } => Err(BFError::CodeChanged),
             ItpState::Uncontrolled(_) => {
                 self.set_code(code);
                 self.send_hw(HWCmd::Program);
@@ -177,6 +245,254 @@ impl Global {
         }
     }
 
+    pub fn change_machine(&self, machine: MachineConfig) -> BFRes {
+        if !machine.is_valid() {
+            return Err(BFError::InvalidMachine);
+        }
+        match *self.state.read().unwrap() {
+            ItpState::Startup
+            | ItpState::Running { .. }
+            | ItpState::Edge { .. }
+            | ItpState::Replay { .. } => Err(BFError::MachineChanged),
+            ItpState::Idle | ItpState::Uncontrolled(_) => {
+                *self.machine.write().unwrap() = machine;
+                Ok(())
+            }
+        }
+    }
+
+    /// save code, input, output and the active run
+    pub fn snapshot(&self) -> Snapshot {
+        let run = match *self.state.read().unwrap() {
+            ItpState::Running { ref run, .. } => Some(run.snapshot()),
+            _ => None,
+        };
+        Snapshot::new(
+            self.full_code.read().unwrap().clone(),
+            self.input.read().unwrap().clone(),
+            self.output.read().unwrap().clone(),
+            run,
+        )
+    }
+
+    /// restore a saved session
+    ///
+    /// a saved run is resumed paused, which is only possible without hw interpreter
+    pub fn restore(&self, snapshot: Snapshot) -> BFRes {
+        let run = snapshot.restore_run().map_err(BFError::InvalidSnapshot)?;
+        if run.is_some() && !hw::TWIN_ONLY {
+            return Err(BFError::HardwareAttached);
+        }
+        match *self.state.read().unwrap() {
+            ItpState::Idle => {}
+            ItpState::Startup => return Err(BFError::StillStarting),
+            ItpState::Running { .. } | ItpState::Edge { .. } | ItpState::Replay { .. } => {
+                return Err(BFError::ItpRunning)
+            }
+            ItpState::Uncontrolled(_) => return Err(BFError::ItpUncontrolled),
+        }
+        self.set_code(snapshot.code);
+        self.set_input(snapshot.input);
+        let Some(mut run) = run else {
+            self.set_output(snapshot.output);
+            return Ok(());
+        };
+        self.set_output(String::from_utf8_lossy(&run.out).into_owned());
+        // unwrap: a run was restored from it
+        *self.machine.write().unwrap() = snapshot.run.unwrap().machine;
+        *self.hw_state.write().unwrap() = HWState::Regular;
+        run.set_breakpoints(self.breakpoint_positions());
+        run.set_watchpoints(self.watchpoints.read().unwrap().clone());
+        self.set_state(ItpState::Running {
+            run: Box::new(run),
+            paused: true,
+        });
+        Ok(())
+    }
+
+    /// all breakpoint positions, including `#` if enabled
+    fn breakpoint_positions(&self) -> Vec<usize> {
+        let mut positions: Vec<_> = self.breakpoints.read().unwrap().iter().copied().collect();
+        if *self.hash_breakpoints.read().unwrap() {
+            positions.extend(bf_itp::debug::hash_positions(&self.full_code.read().unwrap()));
+        }
+        positions
+    }
+
+    /// apply changed breakpoints to an active run
+    fn update_breakpoints(&self) {
+        let positions = self.breakpoint_positions();
+        if let ItpState::Running { ref mut run, .. } = *self.state.write().unwrap() {
+            run.set_breakpoints(positions);
+        }
+    }
+
+    pub fn set_breakpoints(&self, positions: BTreeSet<usize>) {
+        *self.breakpoints.write().unwrap() = positions;
+        self.update_breakpoints();
+    }
+
+    pub fn set_breakpoint(&self, position: usize, enabled: bool) {
+        let mut breakpoints = self.breakpoints.write().unwrap();
+        if enabled {
+            breakpoints.insert(position);
+        } else {
+            breakpoints.remove(&position);
+        }
+        drop(breakpoints);
+        self.update_breakpoints();
+    }
+
+    pub fn set_hash_breakpoints(&self, enabled: bool) {
+        *self.hash_breakpoints.write().unwrap() = enabled;
+        self.update_breakpoints();
+    }
+
+    pub fn set_watchpoints(&self, watchpoints: Vec<Watchpoint>) {
+        *self.watchpoints.write().unwrap() = watchpoints.clone();
+        if let ItpState::Running { ref mut run, .. } = *self.state.write().unwrap() {
+            run.set_watchpoints(watchpoints);
+        }
+    }
+
+    /// path of a recorded trace
+    ///
+    /// returns `None` for names that could point outside of [`TRACE_DIR`]
+    pub fn trace_path(name: &str) -> Option<PathBuf> {
+        let valid = !name.starts_with('.') && !name.contains(['/', '\\']);
+        valid.then(|| Path::new(TRACE_DIR).join(name))
+    }
+
+    /// names of all recorded traces
+    pub fn traces(&self) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(TRACE_DIR) else {
+            return Vec::new();
+        };
+        let mut names: Vec<_> = entries
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// tasks from [`TASKS_FILE`], skipping those that fail [`Task::validate`]
+    fn load_tasks() -> Vec<Task> {
+        let Ok(tasks) = fs::read_to_string(TASKS_FILE) else {
+            return Vec::new();
+        };
+        let tasks: Vec<Task> = serde_json::from_str(&tasks).expect("invalid tasks");
+        tasks
+            .into_iter()
+            .filter_map(|mut task| match task.validate() {
+                Ok(()) => Some(task),
+                Err(err) => {
+                    eprintln!("skipping task {}: {err}", task.name);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// create a new trace file for a run of `source` on a machine with `config`
+    fn create_trace(source: &str, config: &MachineConfig) -> io::Result<TraceWriter> {
+        fs::create_dir_all(TRACE_DIR)?;
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let file = File::create(Path::new(TRACE_DIR).join(format!("trace-{millis}.bftrace")))?;
+        TraceWriter::new(BufWriter::new(file), source, config)
+    }
+
+    /// start playing back a recorded trace while idle
+    ///
+    /// the replay starts paused and replaces code and output
+    pub fn start_replay(&self, name: &str) -> BFRes {
+        let path = Self::trace_path(name).ok_or(BFError::TraceNotFound)?;
+        let file = File::open(path).map_err(|_| BFError::TraceNotFound)?;
+        let replay = TraceReplay::new(BufReader::new(file)).map_err(BFError::InvalidTrace)?;
+        match *self.state.read().unwrap() {
+            ItpState::Idle => {}
+            ItpState::Startup => return Err(BFError::StillStarting),
+            ItpState::Running { .. } | ItpState::Edge { .. } | ItpState::Replay { .. } => {
+                return Err(BFError::ItpRunning)
+            }
+            ItpState::Uncontrolled(_) => return Err(BFError::ItpUncontrolled),
+        }
+        self.set_code(replay.source().to_string());
+        self.set_output(String::new());
+        *self.hw_state.write().unwrap() = HWState::Regular;
+        self.set_state(ItpState::Replay {
+            replay: Box::new(replay),
+            paused: true,
+        });
+        Ok(())
+    }
+
+    /// start a challenge with a random target
+    pub fn create_challenge(&self, profile: ScoringProfile) -> Challenge {
+        let mut challenges = self.challenges.write().unwrap();
+        let id = challenges.last_key_value().map_or(0, |(id, _)| id + 1);
+        let challenge = Challenge::new(id, skill::generate_target(), profile);
+        challenges.insert(id, challenge.clone());
+        if challenges.len() > MAX_CHALLENGES {
+            challenges.pop_first();
+        }
+        challenge
+    }
+
+    pub fn challenge(&self, id: u64) -> Result<Challenge, BFError> {
+        let challenges = self.challenges.read().unwrap();
+        challenges
+            .get(&id)
+            .cloned()
+            .ok_or(BFError::ChallengeNotFound)
+    }
+
+    /// keep a scored submission if it is the best one, returns whether it is
+    pub fn record_submission(&self, id: u64, code: &str, score: Score) -> Result<bool, BFError> {
+        let mut challenges = self.challenges.write().unwrap();
+        let challenge = challenges.get_mut(&id).ok_or(BFError::ChallengeNotFound)?;
+        Ok(challenge.record(code, score))
+    }
+
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+
+    pub fn task(&self, name: &str) -> Result<&Task, BFError> {
+        self.tasks.iter().find(|task| task.name == name).ok_or(BFError::TaskNotFound)
+    }
+
+    /// add a scored submission to the leaderboard, returns whether it was stored
+    ///
+    /// blank names are submitted as `anonymous`.
+    /// waits for the disk, so async callers should use a blocking task
+    pub fn add_to_leaderboard(&self, name: &str, target: &str, code: &str, score: Score) -> bool {
+        let name: String = name.trim().chars().take(MAX_NAME_LEN).collect();
+        let name = if name.is_empty() { "anonymous" } else { &name };
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let entry = Entry::new(name, target, code, score, secs);
+        self.leaderboard.add(entry).unwrap_or_else(|err| {
+            eprintln!("cannot save submission: {err}");
+            false
+        })
+    }
+
+    /// best submissions scored by `profile` for `target`, or for all targets
+    pub fn leaderboard(
+        &self,
+        target: Option<&str>,
+        profile: &ScoringProfile,
+        offset: usize,
+        limit: usize,
+    ) -> Page {
+        self.leaderboard.ranking(target, profile, offset, limit)
+    }
+
     pub fn get_state(&self) -> Value {
         let run_state = match *self.hw_state.read().unwrap() {
             HWState::Regular => "default",
@@ -186,7 +502,9 @@ impl Global {
         let ctrl_state = match *self.state.read().unwrap() {
             ItpState::Idle => "idle",
             ItpState::Startup => "startup",
-            ItpState::Running { paused, .. } => {
+            ItpState::Running { paused, .. }
+            | ItpState::Edge { paused, .. }
+            | ItpState::Replay { paused, .. } => {
                 if paused {
                     "paused"
                 } else {
@@ -195,13 +513,25 @@ impl Global {
             }
             ItpState::Uncontrolled(_) => "uncontrolled",
         };
-        let code_prev: Vec<_> = self.full_code.read().unwrap().chars().filter(|c| ['+', '-', '<', '>', '[', ']', ',', '.'].contains(c)).collect();
-        let code_view = CodeView::new(&code_prev, 0);
+        // code is checked when set, so this always succeeds
+        let (code, source_map) =
+            bf_itp::parse_with_source_map(&self.full_code.read().unwrap()).unwrap_or_default();
+        let code_view = CodeView::new(&code, &source_map, 0);
         let no_run = json!({"control_state": ctrl_state, "code": code_view});
-        if let ItpState::Running { ref run, .. } = *self.state.read().unwrap() {
-            serde_json::to_value(run.view(ctrl_state, run_state)).unwrap_or_else(|_| no_run)
-        } else {
-            no_run
+        match *self.state.read().unwrap() {
+            ItpState::Running { ref run, .. } => {
+                serde_json::to_value(run.view(ctrl_state, run_state)).unwrap_or(no_run)
+            }
+            ItpState::Edge { ref run, .. } => {
+                serde_json::to_value(run.view(ctrl_state, run_state)).unwrap_or(no_run)
+            }
+            ItpState::Replay { ref replay, .. } => {
+                let mut view =
+                    serde_json::to_value(replay.view(ctrl_state, run_state)).unwrap_or(no_run);
+                view["replay"] = json!(true);
+                view
+            }
+            _ => no_run,
         }
     }
 
@@ -210,18 +540,67 @@ impl Global {
         self.hw.send(cmd).expect("hardware thread died");
     }
 
+    /// whether a new run can use the edge-accurate model, if it is enabled
+    ///
+    /// the model always runs on the physical machine with blocking input,
+    /// without breakpoints, watchpoints or traces
+    pub fn check_edge_accurate(&self) -> BFRes {
+        if !*self.edge_accurate.read().unwrap() {
+            return Ok(());
+        }
+        let machine = MachineConfig {
+            eof: EofPolicy::Block,
+            ..*self.machine.read().unwrap()
+        };
+        let physical = MachineConfig {
+            eof: EofPolicy::Block,
+            ..MachineConfig::default()
+        };
+        if machine != physical
+            || !self.breakpoint_positions().is_empty()
+            || !self.watchpoints.read().unwrap().is_empty()
+            || *self.trace.read().unwrap()
+        {
+            return Err(BFError::EdgeUnsupported);
+        }
+        Ok(())
+    }
+
     /// start digital twin
     ///
-    /// clear output and set state to [`ItpState::Running`]
+    /// clear output and set state to [`ItpState::Running`],
+    /// or [`ItpState::Edge`] if the edge-accurate model is enabled
     ///
     /// to be called by the hw_runner thread when the hw interpreter was started successfully
     pub fn itp_started(&self, paused: bool) {
         self.set_output(String::new());
         *self.hw_state.write().unwrap() = HWState::Regular;
-        let code = &*self.full_code.read().unwrap();
-        let input = &*self.input.read().unwrap();
-        let run = Run::new(code, input).expect("code should have already been checked");
-        self.set_state(ItpState::Running { run, paused });
+        // cloned, so no guard is held while breakpoint_positions reads the code again
+        let code = self.full_code.read().unwrap().clone();
+        let input = self.input.read().unwrap().clone();
+        if *self.edge_accurate.read().unwrap() {
+            let run = EdgeRun::new(&code, &input).expect("code should have already been checked");
+            self.set_state(ItpState::Edge {
+                run: Box::new(run),
+                paused,
+            });
+            println!("run started");
+            return;
+        }
+        let machine = *self.machine.read().unwrap();
+        let mut run = Run::new(&code, &input, machine).expect("code should have already been checked");
+        run.set_breakpoints(self.breakpoint_positions());
+        run.set_watchpoints(self.watchpoints.read().unwrap().clone());
+        if *self.trace.read().unwrap() {
+            match Self::create_trace(&code, &machine) {
+                Ok(trace) => run.record_trace(trace),
+                Err(err) => eprintln!("cannot record trace: {err}"),
+            }
+        }
+        self.set_state(ItpState::Running {
+            run: Box::new(run),
+            paused,
+        });
         println!("run started");
     }
 }
@@ -233,7 +612,11 @@ pub enum BFError {
     /// change input while itp running
     InputChanged,
     /// code is not correctly nested
-    InvalidNesting,
+    InvalidNesting(ParseError),
+    /// machine config cannot be used
+    InvalidMachine,
+    /// trying to change machine config while itp is running
+    MachineChanged,
     /// tried to apply control when control is disabled
     ItpUncontrolled,
     /// interpreter already running
@@ -244,6 +627,30 @@ pub enum BFError {
     MissingCode,
     /// waiting for startup to finish
     StillStarting,
+    /// the hw interpreter cannot mirror the requested action
+    HardwareAttached,
+    /// cycle to seek to is not a number
+    InvalidCycle,
+    /// uploaded snapshot cannot be restored
+    InvalidSnapshot(SnapshotError),
+    /// no recorded trace with this name
+    TraceNotFound,
+    /// recorded trace cannot be read
+    InvalidTrace(io::Error),
+    /// action is not possible while playing back a trace
+    Replaying,
+    /// action is not supported by the edge-accurate model
+    EdgeAccurate,
+    /// machine config or debugging settings the edge-accurate model cannot run with
+    EdgeUnsupported,
+    /// no skill challenge with this id
+    ChallengeNotFound,
+    /// no task with this name
+    TaskNotFound,
+    /// unknown scoring profile or unusable weights
+    InvalidProfile,
+    /// format width or indent is too large
+    InvalidFormatOptions,
 }
 
 impl<'r> Responder<'r, 'static> for BFError {
@@ -257,14 +664,70 @@ impl<'r> Responder<'r, 'static> for BFError {
                 Status::UnprocessableEntity,
                 "cannot change already read input during run",
             ),
-            BFError::InvalidNesting => {
-                (Status::UnprocessableEntity, "code is not correctly nested")
+            BFError::InvalidSnapshot(err) => {
+                let body = err.to_string();
+                return Response::build()
+                    .header(ContentType::Plain)
+                    .sized_body(body.len(), Cursor::new(body))
+                    .status(Status::UnprocessableEntity)
+                    .ok();
+            }
+            BFError::InvalidTrace(err) => {
+                let body = format!("invalid trace: {err}");
+                return Response::build()
+                    .header(ContentType::Plain)
+                    .sized_body(body.len(), Cursor::new(body))
+                    .status(Status::UnprocessableEntity)
+                    .ok();
             }
+            BFError::InvalidNesting(err) => {
+                // sent as json, so the client can point at the exact brackets
+                let body = json!({
+                    "message": "code is not correctly nested",
+                    "unmatched": err.unmatched,
+                })
+                .to_string();
+                return Response::build()
+                    .header(ContentType::JSON)
+                    .sized_body(body.len(), Cursor::new(body))
+                    .status(Status::UnprocessableEntity)
+                    .ok();
+            }
+            BFError::InvalidMachine => (Status::BadRequest, "tape length must be between 1 and 1048576"),
+            BFError::MachineChanged => (
+                Status::UnprocessableEntity,
+                "cannot change machine config while interpreter is running",
+            ),
             BFError::ItpUncontrolled => (Status::BadRequest, "control is currently not enabled"),
             BFError::ItpRunning => (Status::BadRequest, "interpreter is currently running"),
             BFError::ItpNotRunning => (Status::BadRequest, "interpreter is currently not running"),
             BFError::MissingCode => (Status::BadRequest, "enter some code to start a run"),
             BFError::StillStarting => (Status::BadRequest, "interpreter is still starting"),
+            BFError::HardwareAttached => (
+                Status::BadRequest,
+                "only possible while no hw interpreter is attached",
+            ),
+            BFError::InvalidCycle => (Status::UnprocessableEntity, "cycle must be a number"),
+            BFError::TraceNotFound => (Status::NotFound, "no trace with this name"),
+            BFError::ChallengeNotFound => (Status::NotFound, "no challenge with this id"),
+            BFError::TaskNotFound => (Status::NotFound, "no task with this name"),
+            BFError::InvalidFormatOptions => (
+                Status::BadRequest,
+                "width must be at most 1000 and indent at most 16",
+            ),
+            BFError::InvalidProfile => (
+                Status::UnprocessableEntity,
+                "profile must be balanced, shortest or fastest and weights must not be negative or both 0",
+            ),
+            BFError::Replaying => (Status::BadRequest, "not possible while replaying a trace"),
+            BFError::EdgeAccurate => (
+                Status::BadRequest,
+                "not possible with the edge-accurate model",
+            ),
+            BFError::EdgeUnsupported => (
+                Status::BadRequest,
+                "the edge-accurate model needs the physical machine and no breakpoints, watchpoints or traces",
+            ),
         };
         Response::build()
             .header(ContentType::Plain)
@@ -287,9 +750,15 @@ pub enum HWCmd {
     /// can start paused (with /api/ctrl/step) or running (with /api/ctrl/start)
     StartRun(bool),
     /// execute steps
-    /// 
+    ///
+    /// a step of the edge-accurate model is a single clock edge
+    ///
     /// contains number of steps + whether pc should be increased
     ExecStep(usize, bool),
+    /// undo steps of the digital twin
+    StepBack(usize),
+    /// move the digital twin to a cycle
+    Seek(usize),
     /// reset interpreter
     Reset,
 }
@@ -1,22 +1,32 @@
-use std::{fmt::Display, io::Write as _};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    time::{Duration, Instant},
+};
 
-use super::{parse, BFCommand, TAPE_LEN};
+use super::{
+    io::{BufferIo, Io, RunError, StreamIo},
+    machine::{BoundaryPolicy, MachineConfig, MachineError, OverflowPolicy, Tape},
+    parse, BFCommand, ParseError, RunStats,
+};
 
 pub struct Prog {
     pub code: Vec<BFCommandOpt>,
     input: bool,
     pub len: usize,
+    pub config: MachineConfig,
 }
 
 impl Prog {
-    pub fn new(code: &str) -> Option<Prog> {
+    pub fn new(code: &str, config: MachineConfig) -> Result<Prog, ParseError> {
         let parsed = parse(code)?;
         let input = parsed.contains(&BFCommand::In);
         let len = parsed.len();
-        Some(Prog {
-            code: optimise(parsed),
+        Ok(Prog {
+            code: optimise(parsed, config),
             input,
             len,
+            config,
         })
     }
 
@@ -24,60 +34,218 @@ impl Prog {
         self.input
     }
 
-    pub fn run(&self, inp: &str) -> (usize, Vec<u8>) {
-        run(inp, &self.code)
+    pub fn run(&self, io: &mut impl Io) -> Result<RunStats, RunError> {
+        run(io, &self.code, self.config)
     }
 
-    pub fn run_full(&self) {
-        let mut inp = String::new();
-        if self.has_input() {
-            print!("Input: ");
-            std::io::stdout().flush().expect("failed to flush");
-            std::io::stdin()
-                .read_line(&mut inp)
-                .expect("failed to get user input");
+    /// run with the whole input known up front, returns the output as well
+    pub fn run_bytes(&self, inp: &[u8]) -> Result<(RunStats, Vec<u8>), MachineError> {
+        let mut io = BufferIo::new(inp);
+        let stats = self.run(&mut io).map_err(RunError::into_machine)?;
+        Ok((stats, io.out))
+    }
+
+    /// run untrusted code with the whole input known up front,
+    /// stopping at the first of the `limits` it runs into
+    pub fn run_with_limits(
+        &self,
+        inp: &[u8],
+        limits: Limits,
+    ) -> Result<(RunOutput, Outcome), MachineError> {
+        let mut io = BufferIo::new(inp);
+        let (res, stop) = run_limited(&mut io, &self.code, self.config, limits)
+            .map_err(RunError::into_machine)?;
+        Ok((res, stop.with_output(io.out)))
+    }
+
+    /// run interactively on stdin and stdout
+    pub fn run_stdio(&self) -> Result<RunStats, RunError> {
+        let mut io = StreamIo::new(std::io::stdin().lock(), std::io::stdout().lock());
+        self.run(&mut io)
+    }
+}
+
+/// limits of a run, see [`Prog::run_with_limits`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// cycles of the hw interpreter
+    pub cycles: usize,
+    /// bytes of output
+    pub output: usize,
+    /// wall-clock time, checked every few thousand ops
+    pub time: Option<Duration>,
+}
+
+impl Limits {
+    pub const NONE: Limits = Limits {
+        cycles: usize::MAX,
+        output: usize::MAX,
+        time: None,
+    };
+}
+
+/// final state of a run
+#[derive(Debug)]
+pub struct RunOutput {
+    pub stats: RunStats,
+    pub tape: Tape,
+    /// position of the head at the end of the run
+    pub head: usize,
+}
+
+/// how a run with [`Limits`] ended, with the output written until then
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Finished(Vec<u8>),
+    /// the run needed more cycles than its budget
+    OutOfFuel(Vec<u8>),
+    /// the run tried to write more bytes than allowed
+    OutputLimit(Vec<u8>),
+    TimedOut(Vec<u8>),
+}
+
+impl Outcome {
+    pub fn output(&self) -> &[u8] {
+        match self {
+            Outcome::Finished(out)
+            | Outcome::OutOfFuel(out)
+            | Outcome::OutputLimit(out)
+            | Outcome::TimedOut(out) => out,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Outcome::Finished(_))
+    }
+}
+
+/// why a limited run stopped, see [`Outcome`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stop {
+    Finished,
+    OutOfFuel,
+    OutputLimit,
+    TimedOut,
+}
+
+impl Stop {
+    pub(crate) fn with_output(self, out: Vec<u8>) -> Outcome {
+        match self {
+            Stop::Finished => Outcome::Finished(out),
+            Stop::OutOfFuel => Outcome::OutOfFuel(out),
+            Stop::OutputLimit => Outcome::OutputLimit(out),
+            Stop::TimedOut => Outcome::TimedOut(out),
+        }
+    }
+}
+
+/// ops between two looks at the clock
+const TIME_CHECK_INTERVAL: usize = 4096;
+
+/// checks the [`Limits`] of a running program
+pub(crate) struct Budget {
+    limits: Limits,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    pub(crate) fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            // a deadline too far out to represent is no deadline
+            deadline: limits
+                .time
+                .and_then(|time| Instant::now().checked_add(time)),
+        }
+    }
+
+    pub(crate) fn may_write(&self, stats: &RunStats) -> bool {
+        stats.written < self.limits.output
+    }
+
+    /// steps a scan may take before it runs out of cycles,
+    /// which bounds scans on a wrapping tape without zero cells
+    pub(crate) fn scan_steps(&self, stats: &RunStats, stride: usize) -> usize {
+        self.limits.cycles.saturating_sub(stats.cycles) / (stride + 1) + 1
+    }
+
+    /// the limit the run went past, checked after every op
+    pub(crate) fn exceeded(&self, stats: &RunStats) -> Option<Stop> {
+        if stats.cycles > self.limits.cycles {
+            Some(Stop::OutOfFuel)
+        } else if stats.steps % TIME_CHECK_INTERVAL == 0
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Some(Stop::TimedOut)
+        } else {
+            None
         }
-        let (_, out) = self.run(&inp);
-        let out = String::from_utf8(out).unwrap_or("output contains invalid utf8".to_string());
-        println!("{out}");
     }
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum BFCommandOpt {
-    Inc(u8),
-    Dec(u8),
-    Left(usize),
-    Right(usize),
-    In,
-    Out,
+    /// add to the cell at an offset from the head
+    Add(isize, i64),
+    /// move the head, with the number of `<` / `>` it replaces
+    Move(isize, usize),
+    /// read into the cell at an offset
+    In(isize),
+    /// write the cell at an offset
+    Out(isize),
     LoopStart,
     LoopEnd,
-    SetZero,
-    ArithLoop(Vec<(isize, u8)>, usize),
+    /// `[-]` on the cell at an offset
+    SetZero(isize),
+    /// `[-]` followed by adding the value to the cell at an offset
+    Set(isize, i64),
+    /// loop with its counter at an offset, decrementing it by one per iteration
+    ///
+    /// adds a multiple of the counter to the cells at the offsets (relative to the counter)
+    /// and takes the given cycles per iteration. the last field is the length of its source
+    ArithLoop(isize, Vec<(isize, i64)>, usize, usize),
+    /// `[>]`, `[<<]` etc, moving the head by the stride until it reaches a zero cell
+    Scan(isize),
+    /// all but the first iteration of the enclosing loop, whose counter is under the head
+    ///
+    /// used for loops with nested loops that run a constant number of times,
+    /// but only after the first iteration
+    Repeat(Vec<(isize, i64)>, usize),
+    /// removed code, only taking the cycles of passing through it
+    Nop(usize),
+}
+
+fn fmt_parts(parts: &[(isize, i64)]) -> String {
+    parts
+        .iter()
+        .map(|x| format!("({}, {})", x.0, x.1))
+        .reduce(|x, y| format!("{x}, {y}"))
+        .unwrap_or_default()
 }
 
 impl Display for BFCommandOpt {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            BFCommandOpt::Inc(by) => write!(f, "{}", "+".repeat(*by as usize)),
-            BFCommandOpt::Dec(by) => write!(f, "{}", "-".repeat(*by as usize)),
-            BFCommandOpt::Left(by) => write!(f, "{}", "<".repeat(*by)),
-            BFCommandOpt::Right(by) => write!(f, "{}", ">".repeat(*by)),
-            BFCommandOpt::In => write!(f, ","),
-            BFCommandOpt::Out => write!(f, "."),
+            BFCommandOpt::Add(offset, by) => write!(f, "{{{offset}:{by:+}}}"),
+            BFCommandOpt::Move(by, _) if *by < 0 => write!(f, "{{<{}}}", -by),
+            BFCommandOpt::Move(by, _) => write!(f, "{{>{by}}}"),
+            BFCommandOpt::In(offset) => write!(f, "{{{offset}:,}}"),
+            BFCommandOpt::Out(offset) => write!(f, "{{{offset}:.}}"),
             BFCommandOpt::LoopStart => write!(f, "["),
             BFCommandOpt::LoopEnd => write!(f, "]"),
-            BFCommandOpt::SetZero => write!(f, "{{_}}"),
-            BFCommandOpt::ArithLoop(vec, cycles) => write!(
-                f,
-                "{{[{}], {}}}",
-                vec.iter()
-                    .map(|x| format!("({}, {})", x.0, x.1))
-                    .reduce(|x, y| format!("{x}, {y}"))
-                    .unwrap_or_default(),
-                cycles
-            ),
+            BFCommandOpt::SetZero(offset) => write!(f, "{{{offset}:_}}"),
+            BFCommandOpt::Set(offset, value) => write!(f, "{{{offset}:={value}}}"),
+            BFCommandOpt::ArithLoop(offset, parts, cycles, _) => {
+                write!(f, "{{{offset}:[{}], {cycles}}}", fmt_parts(parts))
+            }
+            BFCommandOpt::Scan(stride) if *stride < 0 => write!(f, "{{[<{}]}}", -stride),
+            BFCommandOpt::Scan(stride) => write!(f, "{{[>{stride}]}}"),
+            BFCommandOpt::Repeat(parts, cycles) => {
+                write!(f, "{{*[{}], {cycles}}}", fmt_parts(parts))
+            }
+            BFCommandOpt::Nop(cycles) => write!(f, "{{~{cycles}}}"),
         }
     }
 }
@@ -85,213 +253,625 @@ impl Display for BFCommandOpt {
 impl From<BFCommand> for BFCommandOpt {
     fn from(value: BFCommand) -> Self {
         match value {
-            BFCommand::Inc => BFCommandOpt::Inc(1),
-            BFCommand::Dec => BFCommandOpt::Dec(1),
-            BFCommand::Left => BFCommandOpt::Left(1),
-            BFCommand::Right => BFCommandOpt::Right(1),
-            BFCommand::In => BFCommandOpt::In,
-            BFCommand::Out => BFCommandOpt::Out,
+            BFCommand::Inc => BFCommandOpt::Add(0, 1),
+            BFCommand::Dec => BFCommandOpt::Add(0, -1),
+            BFCommand::Left => BFCommandOpt::Move(-1, 1),
+            BFCommand::Right => BFCommandOpt::Move(1, 1),
+            BFCommand::In => BFCommandOpt::In(0),
+            BFCommand::Out => BFCommandOpt::Out(0),
             BFCommand::LoopStart => BFCommandOpt::LoopStart,
             BFCommand::LoopEnd => BFCommandOpt::LoopEnd,
         }
     }
 }
 
-pub fn optimise(code: Vec<BFCommand>) -> Vec<BFCommandOpt> {
+/// optimisation pipeline
+///
+/// every pass keeps the cycles of the original commands,
+/// so [`run`] still reports the cycles of the unoptimised program.
+/// configs that don't wrap both cells and the tape only get runs folded,
+/// their loops are never rewritten
+pub fn optimise(code: Vec<BFCommand>, config: MachineConfig) -> Vec<BFCommandOpt> {
+    // the passes after folding address cells by their offset from the head, reorder
+    // adds to different cells, drop moves that cancel out and apply loops at once.
+    // that only holds if neither a move nor an add can fail or saturate, and the head
+    // cannot wrap around onto a cell it already addressed by another offset
+    let exact = config.boundary == BoundaryPolicy::Wrap
+        && config.overflow == OverflowPolicy::Wrap
+        && config.tape_len > code.len();
+    let code = fold(code);
+    if !exact {
+        return code;
+    }
+    let code = propagate(code);
+    let code = simplify_loops(code);
+    sink_moves(code)
+}
+
+/// combine runs of the same command
+fn fold(code: Vec<BFCommand>) -> Vec<BFCommandOpt> {
+    let mut res: Vec<BFCommandOpt> = Vec::new();
+    for cmd in code {
+        match (res.last_mut(), cmd) {
+            (Some(BFCommandOpt::Add(0, by)), BFCommand::Inc) if *by > 0 => *by += 1,
+            (Some(BFCommandOpt::Add(0, by)), BFCommand::Dec) if *by < 0 => *by -= 1,
+            (Some(BFCommandOpt::Move(by, moves)), BFCommand::Right) if *by > 0 => {
+                *by += 1;
+                *moves += 1;
+            }
+            (Some(BFCommandOpt::Move(by, moves)), BFCommand::Left) if *by < 0 => {
+                *by -= 1;
+                *moves += 1;
+            }
+            _ => res.push(cmd.into()),
+        }
+    }
+    res
+}
+
+/// cell values known at some point of the program, relative to a fixed head position
+struct KnownCells {
+    cells: HashMap<isize, Option<i64>>,
+    /// value of all other cells
+    rest: Option<i64>,
+}
+
+impl KnownCells {
+    fn get(&self, offset: isize) -> Option<i64> {
+        self.cells.get(&offset).copied().unwrap_or(self.rest)
+    }
+
+    /// values have to fit every cell width to be known
+    fn set(&mut self, offset: isize, value: Option<i64>) {
+        let value = value.filter(|v| (0..=u8::MAX as i64).contains(v));
+        self.cells.insert(offset, value);
+    }
+
+    fn unknown() -> Self {
+        Self {
+            cells: HashMap::new(),
+            rest: None,
+        }
+    }
+}
+
+/// known-cell-value dataflow on folded code
+///
+/// the tape starts zeroed and every loop leaves a zero cell under the head,
+/// so loops on a known zero cell can never run and are removed.
+/// opposite runs on a known cell cancel out, as no cell width or overflow policy
+/// can tell them apart, and opposite moves always do.
+/// removed code is replaced by a [`BFCommandOpt::Nop`] with its cycles
+fn propagate(code: Vec<BFCommandOpt>) -> Vec<BFCommandOpt> {
     let mut res = Vec::new();
-    let mut code = code.into_iter().peekable();
-    let Some(first) = code.next() else {
-        return Vec::new();
+    let mut known = KnownCells {
+        cells: HashMap::new(),
+        rest: Some(0),
     };
-    let mut curr: BFCommandOpt = first.into();
-    while let Some(cmd) = code.next() {
-        match &mut curr {
-            BFCommandOpt::Inc(by) => {
-                if cmd == BFCommand::Inc {
-                    *by += 1;
-                    continue;
-                }
-            }
-            BFCommandOpt::Dec(by) => {
-                if cmd == BFCommand::Dec {
-                    *by += 1;
-                    continue;
+    let mut pos = 0_isize;
+    let mut code = code.into_iter();
+    while let Some(op) = code.next() {
+        match op {
+            BFCommandOpt::LoopStart if known.get(pos) == Some(0) => {
+                // the twin passes through a skipped loop one command per cycle
+                let mut depth = 1;
+                let mut cycles = 1;
+                for op in code.by_ref() {
+                    cycles += raw_len(&op);
+                    match op {
+                        BFCommandOpt::LoopStart => depth += 1,
+                        BFCommandOpt::LoopEnd => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
                 }
+                push_nop(&mut res, cycles);
             }
-            BFCommandOpt::Left(by) => {
-                if cmd == BFCommand::Left {
-                    *by += 1;
-                    continue;
+            BFCommandOpt::LoopStart | BFCommandOpt::LoopEnd => {
+                // the body may run any number of times, but always exits on a zero cell
+                known = KnownCells::unknown();
+                pos = 0;
+                if op == BFCommandOpt::LoopEnd {
+                    known.set(0, Some(0));
                 }
+                res.push(op);
             }
-            BFCommandOpt::Right(by) => {
-                if cmd == BFCommand::Right {
-                    *by += 1;
-                    continue;
+            BFCommandOpt::Add(offset, by) => {
+                let value = known.get(pos + offset);
+                known.set(pos + offset, value.map(|v| v + by));
+                let cancels = value.is_some() && known.get(pos + offset).is_some();
+                // a nop never moves the head
+                let last = match res[..] {
+                    [.., BFCommandOpt::Nop(_)] => res.len().checked_sub(2),
+                    _ => res.len().checked_sub(1),
+                };
+                match last.map(|i| (i, &mut res[i])) {
+                    // the value before the previous add is known as well
+                    Some((i, BFCommandOpt::Add(prev_offset, prev)))
+                        if cancels && *prev_offset == offset && prev.signum() != by.signum() =>
+                    {
+                        let cycles = prev.unsigned_abs() + by.unsigned_abs();
+                        *prev += by;
+                        let cycles = (cycles - prev.unsigned_abs()) as usize;
+                        if *prev == 0 {
+                            res.remove(i);
+                        }
+                        push_nop(&mut res, cycles);
+                    }
+                    _ => res.push(op),
                 }
             }
-            BFCommandOpt::LoopStart => {
-                //res.push(curr);
-                let mut inner = vec![cmd];
-                let mut success = false;
-                while let Some(cmd) = code.peek() {
-                    match cmd {
-                        BFCommand::LoopEnd => {
-                            success = true;
-                            break;
+            BFCommandOpt::Move(by, moves) => {
+                pos += by;
+                match res.last_mut() {
+                    Some(BFCommandOpt::Move(prev, prev_moves)) if prev.signum() != by.signum() => {
+                        *prev += by;
+                        *prev_moves += moves;
+                        if *prev == 0 {
+                            let cycles = *prev_moves;
+                            res.pop();
+                            push_nop(&mut res, cycles);
                         }
-                        BFCommand::LoopStart => break,
-                        _ => inner.push(code.next().unwrap()), // unwrap: peeked successfully
                     }
+                    _ => res.push(op),
                 }
-                let mut inner_opt = optimise(inner);
-                if success {
-                    if let Some(arith) = arithmetic_loop(&inner_opt) {
-                        res.push(arith);
-                        // consume LoopEnd
-                        code.next();
-                    } else {
-                        res.push(curr);
-                        res.append(&mut inner_opt);
+            }
+            BFCommandOpt::In(offset) => {
+                known.set(pos + offset, None);
+                res.push(op);
+            }
+            BFCommandOpt::Out(_) => res.push(op),
+            BFCommandOpt::Nop(cycles) => push_nop(&mut res, cycles),
+            _ => unreachable!("only folded code is propagated"),
+        }
+    }
+    res
+}
+
+/// cycles of passing over an op while skipping an enclosing loop,
+/// one per command of its source
+pub(crate) fn raw_len(op: &BFCommandOpt) -> usize {
+    match *op {
+        BFCommandOpt::Add(_, by) => by.unsigned_abs() as usize,
+        BFCommandOpt::Move(_, moves) => moves,
+        BFCommandOpt::In(_)
+        | BFCommandOpt::Out(_)
+        | BFCommandOpt::LoopStart
+        | BFCommandOpt::LoopEnd => 1,
+        BFCommandOpt::SetZero(_) => 3,
+        BFCommandOpt::Set(_, value) => 3 + value as usize,
+        BFCommandOpt::ArithLoop(.., len) => len,
+        BFCommandOpt::Scan(stride) => stride.unsigned_abs() + 2,
+        // not part of the source, the enclosing loop is skipped as a whole
+        BFCommandOpt::Repeat(..) => 0,
+        BFCommandOpt::Nop(cycles) => cycles,
+    }
+}
+
+/// cycles of a loop that runs `count` times, taking `cycles` per iteration
+///
+/// a skipped loop passes through all `len` commands of its source instead
+pub(crate) fn loop_cycles(count: usize, cycles: usize, len: usize) -> usize {
+    match count {
+        0 => len,
+        _ => 1 + cycles * count,
+    }
+}
+
+fn push_nop(code: &mut Vec<BFCommandOpt>, cycles: usize) {
+    match code.last_mut() {
+        Some(BFCommandOpt::Nop(prev)) => *prev += cycles,
+        _ => code.push(BFCommandOpt::Nop(cycles)),
+    }
+}
+
+/// sink all pointer movement of a basic block into a single move at its end
+///
+/// the other commands address their cells by offset instead.
+/// adds to the same cell are combined if they have the same sign.
+/// positive adds right after a `[-]` turn it into a [`BFCommandOpt::Set`]
+fn sink_moves(code: Vec<BFCommandOpt>) -> Vec<BFCommandOpt> {
+    let mut res = Vec::new();
+    let mut pos = 0_isize;
+    let mut moves = 0_usize;
+    // index of the last add to an offset, while nothing else touched the cell since
+    let mut adds: HashMap<isize, usize> = HashMap::new();
+    for op in code {
+        match op {
+            BFCommandOpt::Move(by, count) => {
+                pos += by;
+                moves += count;
+            }
+            BFCommandOpt::Add(offset, by) => {
+                let offset = pos + offset;
+                match adds.get(&offset).map(|&i| &mut res[i]) {
+                    Some(BFCommandOpt::Add(_, prev)) if prev.signum() == by.signum() => *prev += by,
+                    // the value has to fit every cell width
+                    Some(BFCommandOpt::Set(_, value))
+                        if by > 0 && *value + by <= u8::MAX as i64 =>
+                    {
+                        *value += by
+                    }
+                    Some(op @ BFCommandOpt::SetZero(_)) if (1..=u8::MAX as i64).contains(&by) => {
+                        *op = BFCommandOpt::Set(offset, by)
+                    }
+                    _ => {
+                        adds.insert(offset, res.len());
+                        res.push(BFCommandOpt::Add(offset, by));
                     }
-                } else {
-                    res.push(curr);
-                    res.append(&mut inner_opt);
                 }
-                let Some(next) = code.next() else {
-                    return res;
-                };
-                curr = next.into();
-                continue;
             }
-            _ => {}
+            BFCommandOpt::SetZero(offset) => {
+                adds.insert(pos + offset, res.len());
+                res.push(BFCommandOpt::SetZero(pos + offset));
+            }
+            BFCommandOpt::Set(offset, value) => {
+                adds.insert(pos + offset, res.len());
+                res.push(BFCommandOpt::Set(pos + offset, value));
+            }
+            BFCommandOpt::Nop(cycles) => push_nop(&mut res, cycles),
+            // io must stay in order with everything that could abort the run
+            BFCommandOpt::In(offset) => {
+                adds.clear();
+                res.push(BFCommandOpt::In(pos + offset));
+            }
+            BFCommandOpt::Out(offset) => {
+                adds.clear();
+                res.push(BFCommandOpt::Out(pos + offset));
+            }
+            BFCommandOpt::ArithLoop(offset, parts, cycles, len) => {
+                adds.clear();
+                res.push(BFCommandOpt::ArithLoop(pos + offset, parts, cycles, len));
+            }
+            // end of the basic block
+            BFCommandOpt::LoopStart
+            | BFCommandOpt::LoopEnd
+            | BFCommandOpt::Scan(_)
+            | BFCommandOpt::Repeat(..) => {
+                if moves > 0 {
+                    res.push(BFCommandOpt::Move(pos, moves));
+                }
+                pos = 0;
+                moves = 0;
+                adds.clear();
+                res.push(op);
+            }
         }
-        res.push(curr);
-        curr = cmd.into();
     }
-    res.push(curr);
+    if moves > 0 {
+        res.push(BFCommandOpt::Move(pos, moves));
+    }
     res
 }
 
-fn arithmetic_loop(code: &[BFCommandOpt]) -> Option<BFCommandOpt> {
-    let mut idx = 0_isize;
-    let mut parts: Vec<(isize, u8)> = Vec::new();
-    let mut cycles = 1;
-    for cmd in code {
-        match cmd {
-            BFCommandOpt::Inc(by) => {
-                if let Some(p) = parts.iter_mut().find(|(i, _)| idx == *i) {
-                    p.1 = p.1.wrapping_add(*by);
-                } else {
-                    parts.push((idx, *by));
-                }
-                cycles += *by as usize;
+/// replace loops by their closed form where possible, innermost first
+fn simplify_loops(code: Vec<BFCommandOpt>) -> Vec<BFCommandOpt> {
+    let mut res = Vec::new();
+    let mut starts = Vec::new();
+    for op in code {
+        match op {
+            BFCommandOpt::LoopStart => {
+                starts.push(res.len());
+                res.push(op);
             }
-            BFCommandOpt::Dec(by) => {
-                if let Some(p) = parts.iter_mut().find(|(i, _)| idx == *i) {
-                    p.1 = p.1.wrapping_sub(*by);
+            BFCommandOpt::LoopEnd => {
+                // code is checked for correct nesting when parsing
+                let start = starts.pop().expect("invalid program");
+                let body = sink_moves(res.split_off(start + 1));
+                if let [BFCommandOpt::Move(by, moves)] = body[..] {
+                    if by != 0 && moves == by.unsigned_abs() {
+                        res.pop();
+                        res.push(BFCommandOpt::Scan(by));
+                        continue;
+                    }
+                }
+                if let Some(arith) = arithmetic_loop(&body) {
+                    res.pop();
+                    res.push(arith);
                 } else {
-                    parts.push((idx, 0_u8.wrapping_sub(*by)));
+                    let repeat = repeat_loop(&body);
+                    res.extend(body);
+                    res.extend(repeat);
+                    res.push(BFCommandOpt::LoopEnd);
                 }
-                cycles += *by as usize;
             }
-            BFCommandOpt::Left(by) => {
-                idx -= *by as isize;
-                cycles += *by;
+            _ => res.push(op),
+        }
+    }
+    res
+}
+
+/// value of a cell while executing a loop body symbolically
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cell {
+    /// independent of the value before the iteration
+    Known(i64),
+    /// changed by a constant
+    Delta(i64),
+    /// depends on other cells
+    Unknown,
+}
+
+impl Cell {
+    fn add(self, by: i64) -> Self {
+        match self {
+            // the value has to fit every cell width to be known
+            Cell::Known(value) if (0..=u8::MAX as i64).contains(&(value + by)) => {
+                Cell::Known(value + by)
+            }
+            Cell::Known(_) | Cell::Unknown => Cell::Unknown,
+            Cell::Delta(delta) => Cell::Delta(delta + by),
+        }
+    }
+}
+
+/// execute a single iteration of a loop body symbolically
+///
+/// `start` holds the cells known before the iteration.
+/// returns the cells afterwards and the cycles of the iteration, if they are constant
+fn iterate(
+    body: &[BFCommandOpt],
+    start: &BTreeMap<isize, Cell>,
+) -> Option<(BTreeMap<isize, Cell>, Option<usize>)> {
+    let mut cells = start.clone();
+    // the closing `]`
+    let mut cycles = Some(1);
+    for op in body {
+        match *op {
+            BFCommandOpt::Add(offset, by) => {
+                let cell = cells.entry(offset).or_insert(Cell::Delta(0));
+                *cell = cell.add(by);
+                cycles = cycles.map(|c| c + by.unsigned_abs() as usize);
+            }
+            BFCommandOpt::Move(0, moves) => cycles = cycles.map(|c| c + moves),
+            BFCommandOpt::SetZero(offset) => {
+                let value = cells.insert(offset, Cell::Known(0));
+                cycles = match value {
+                    Some(Cell::Known(value)) => {
+                        cycles.map(|c| c + loop_cycles(value as usize, 2, 3))
+                    }
+                    _ => None,
+                };
             }
-            BFCommandOpt::Right(by) => {
-                idx += *by as isize;
-                cycles += *by;
+            BFCommandOpt::Set(offset, new) => {
+                let value = cells.insert(offset, Cell::Known(new));
+                cycles = match value {
+                    Some(Cell::Known(value)) => {
+                        cycles.map(|c| c + loop_cycles(value as usize, 2, 3) + new as usize)
+                    }
+                    _ => None,
+                };
+            }
+            BFCommandOpt::Nop(nop) => cycles = cycles.map(|c| c + nop),
+            BFCommandOpt::ArithLoop(offset, ref parts, per_iteration, len) => {
+                let count = cells.insert(offset, Cell::Known(0));
+                let count = match count {
+                    Some(Cell::Known(count)) => Some(count),
+                    _ => None,
+                };
+                for (part, by) in parts {
+                    let cell = cells.entry(offset + part).or_insert(Cell::Delta(0));
+                    *cell = match count {
+                        Some(count) => cell.add(by * count),
+                        None => Cell::Unknown,
+                    };
+                }
+                cycles = cycles
+                    .zip(count)
+                    .map(|(c, n)| c + loop_cycles(n as usize, per_iteration, len));
             }
             _ => return None,
         }
     }
-    if idx != 0 {
+    Some((cells, cycles))
+}
+
+/// parts of a loop that decrements its counter at offset 0 by one,
+/// given the cells after an iteration
+///
+/// cells known before the iteration have to keep their value
+fn loop_parts(
+    start: &BTreeMap<isize, Cell>,
+    cells: BTreeMap<isize, Cell>,
+) -> Option<Vec<(isize, i64)>> {
+    // the counter has to be decremented by exactly one, independent of the cell width
+    if cells.get(&0) != Some(&Cell::Delta(-1)) {
         return None;
     }
-    let old_len = parts.len();
-    parts.retain(|p| *p != (0, u8::MAX));
-    if old_len != parts.len() + 1 {
-        None
-    } else if parts.is_empty() && cycles == 2 {
-        Some(BFCommandOpt::SetZero)
+    let mut parts = Vec::new();
+    for (offset, cell) in cells {
+        match cell {
+            _ if offset == 0 => {}
+            Cell::Delta(0) => {}
+            Cell::Delta(by) => parts.push((offset, by)),
+            Cell::Known(_) if start.get(&offset) == Some(&cell) => {}
+            _ => return None,
+        }
+    }
+    Some(parts)
+}
+
+fn arithmetic_loop(body: &[BFCommandOpt]) -> Option<BFCommandOpt> {
+    let (cells, cycles) = iterate(body, &BTreeMap::new())?;
+    let parts = loop_parts(&BTreeMap::new(), cells)?;
+    let cycles = cycles?;
+    if parts.is_empty() && cycles == 2 {
+        Some(BFCommandOpt::SetZero(0))
     } else {
-        Some(BFCommandOpt::ArithLoop(parts, cycles))
+        let len = 2 + body.iter().map(raw_len).sum::<usize>();
+        Some(BFCommandOpt::ArithLoop(0, parts, cycles, len))
+    }
+}
+
+/// closed form for all but the first iteration of a loop
+///
+/// after the first iteration, cells that are known at the end of every iteration
+/// can be used to count nested loops
+fn repeat_loop(body: &[BFCommandOpt]) -> Option<BFCommandOpt> {
+    let (end, _) = iterate(body, &BTreeMap::new())?;
+    let start: BTreeMap<_, _> = end
+        .into_iter()
+        .filter(|(_, cell)| matches!(cell, Cell::Known(_)))
+        .collect();
+    if start.is_empty() {
+        return None;
     }
+    let (cells, cycles) = iterate(body, &start)?;
+    let parts = loop_parts(&start, cells)?;
+    Some(BFCommandOpt::Repeat(parts, cycles?))
+}
+
+pub fn run(
+    io: &mut impl Io,
+    prog: &[BFCommandOpt],
+    config: MachineConfig,
+) -> Result<RunStats, RunError> {
+    run_limited(io, prog, config, Limits::NONE).map(|(res, _)| res.stats)
 }
 
-pub fn run(inp: &str, prog: &[BFCommandOpt]) -> (usize, Vec<u8>) {
-    // prepare jump table
-    let mut stack: Vec<usize> = Vec::new();
+pub(crate) fn run_limited(
+    io: &mut impl Io,
+    prog: &[BFCommandOpt],
+    config: MachineConfig,
+    limits: Limits,
+) -> Result<(RunOutput, Stop), RunError> {
+    // prepare jump table and the cycles of skipping each loop
+    let mut stack: Vec<(usize, usize)> = Vec::new();
     let mut jmp_table: Vec<_> = prog.iter().map(|_| 0).collect();
+    let mut skip_table: Vec<_> = prog.iter().map(|_| 0).collect();
+    // length of the source before the current op
+    let mut len = 0;
     for i in 0..prog.len() {
         match prog[i] {
-            BFCommandOpt::LoopStart => stack.push(i),
+            BFCommandOpt::LoopStart => stack.push((i, len)),
             BFCommandOpt::LoopEnd => {
-                let other = stack.pop().expect("invalid program");
+                let (other, start_len) = stack.pop().expect("invalid program");
                 jmp_table[other] = i;
                 jmp_table[i] = other;
+                skip_table[other] = len + 1 - start_len;
             }
             _ => {}
         }
+        len += raw_len(&prog[i]);
     }
     // run code
-    let mut inp = inp.as_bytes().iter();
-    let mut out = Vec::new();
-    let mut tape = [0_u8; TAPE_LEN];
+    let budget = Budget::new(limits);
+    let mut stats = RunStats::default();
+    let mut tape = Tape::new(config);
     let mut pc = 0_usize;
     let mut head = 0_usize;
-    let mut cycles = 0_usize;
-    let mut real_cycles = 0_usize;
+    let mut stop = Stop::Finished;
     while pc < prog.len() {
-        cycles += 1;
-        real_cycles += 1;
-        match prog[pc] {
-            BFCommandOpt::Inc(by) => {
-                tape[head] = tape[head].wrapping_add(by);
-                real_cycles += by as usize - 1;
-            }
-            BFCommandOpt::Dec(by) => {
-                tape[head] = tape[head].wrapping_sub(by);
-                real_cycles += by as usize - 1;
-            }
-            BFCommandOpt::Left(by) => {
-                head = (head.wrapping_sub(by)) % TAPE_LEN;
-                real_cycles += by - 1;
-            }
-            BFCommandOpt::Right(by) => {
-                head = (head.wrapping_add(by)) % TAPE_LEN;
-                real_cycles += by - 1;
-            }
-            BFCommandOpt::In => tape[head] = *inp.next().unwrap_or(&0),
-            BFCommandOpt::Out => out.push(tape[head]),
+        stats.steps += 1;
+        stats.cycles += match prog[pc] {
+            BFCommandOpt::Add(offset, by) => {
+                let idx = tape.offset(head, offset)?;
+                tape.add(idx, by)?;
+                by.unsigned_abs() as usize
+            }
+            BFCommandOpt::Move(by, moves) => {
+                head = tape.offset(head, by)?;
+                moves
+            }
+            BFCommandOpt::In(offset) => {
+                let idx = tape.offset(head, offset)?;
+                let value = match io.read()? {
+                    Some(byte) => {
+                        stats.read += 1;
+                        byte as u32
+                    }
+                    // no more input will arrive, so blocking would wait forever
+                    None => config
+                        .eof_value(tape.get(idx))
+                        .ok_or(MachineError::InputExhausted)?,
+                };
+                tape.set(idx, value);
+                1
+            }
+            BFCommandOpt::Out(offset) => {
+                if !budget.may_write(&stats) {
+                    stop = Stop::OutputLimit;
+                    break;
+                }
+                let idx = tape.offset(head, offset)?;
+                // wider cells are truncated to their lowest byte
+                io.write(tape.get(idx) as u8)?;
+                stats.written += 1;
+                1
+            }
             BFCommandOpt::LoopStart => {
-                if tape[head] == 0 {
+                if tape.get(head) == 0 {
+                    let skip = skip_table[pc];
                     pc = jmp_table[pc];
+                    skip
+                } else {
+                    1
                 }
             }
             BFCommandOpt::LoopEnd => {
-                if tape[head] != 0 {
+                if tape.get(head) != 0 {
                     pc = jmp_table[pc];
                 }
+                1
             }
-            BFCommandOpt::SetZero => {
-                real_cycles += 2 * tape[head] as usize;
-                tape[head] = 0;
+            BFCommandOpt::SetZero(offset) => {
+                let idx = tape.offset(head, offset)?;
+                let value = tape.get(idx) as usize;
+                tape.set(idx, 0);
+                loop_cycles(value, 2, 3)
             }
-            BFCommandOpt::ArithLoop(ref parts, cycles) => {
-                real_cycles += cycles * tape[head] as usize;
-                for (offset, value) in parts {
-                    let idx = head.wrapping_add_signed(*offset) % TAPE_LEN;
-                    tape[idx] = tape[idx].wrapping_add(value.wrapping_mul(tape[head]));
-                }
-                tape[head] = 0;
+            BFCommandOpt::Set(offset, new) => {
+                let idx = tape.offset(head, offset)?;
+                let value = tape.get(idx) as usize;
+                tape.set(idx, new as u32);
+                loop_cycles(value, 2, 3) + new as usize
             }
-        }
+            BFCommandOpt::ArithLoop(offset, ref parts, per_iteration, len) => {
+                let idx = tape.offset(head, offset)?;
+                let count = apply_loop(&mut tape, idx, parts)?;
+                loop_cycles(count, per_iteration, len)
+            }
+            BFCommandOpt::Scan(stride) => {
+                let steps;
+                let max_steps = budget.scan_steps(&stats, stride.unsigned_abs());
+                (head, steps) = tape.scan(head, stride, max_steps)?;
+                let stride = stride.unsigned_abs();
+                loop_cycles(steps, stride + 1, stride + 2)
+            }
+            BFCommandOpt::Repeat(ref parts, per_iteration) => {
+                per_iteration * apply_loop(&mut tape, head, parts)?
+            }
+            BFCommandOpt::Nop(cycles) => cycles,
+        };
         pc += 1;
+        if let Some(limit) = budget.exceeded(&stats) {
+            stop = limit;
+            break;
+        }
     }
-    println!("cycles: {cycles} / {real_cycles}");
-    (real_cycles, out)
+    io.flush()?;
+    Ok((RunOutput { stats, tape, head }, stop))
+}
+
+/// run a loop with its counter at `idx` at once, returns the number of iterations
+pub(crate) fn apply_loop(
+    tape: &mut Tape,
+    idx: usize,
+    parts: &[(isize, i64)],
+) -> Result<usize, MachineError> {
+    let count = tape.get(idx);
+    // loops are only simplified on machines that wrap cells,
+    // where applying the whole product at once is exact
+    if count != 0 {
+        for (offset, value) in parts {
+            let part = tape.offset(idx, *offset)?;
+            tape.add(part, value * count as i64)?;
+        }
+        tape.set(idx, 0);
+    }
+    Ok(count as usize)
 }
 
 #[cfg(test)]
@@ -299,7 +879,10 @@ mod test {
     use super::*;
 
     fn test_arith_loop(inp: &str, exp: &str) {
-        let code = optimise(parse(inp).expect("invalid program"));
+        let code = optimise(
+            parse(inp).expect("invalid program"),
+            MachineConfig::default(),
+        );
         let disp = code
             .iter()
             .map(<BFCommandOpt as ToString>::to_string)
@@ -308,10 +891,157 @@ mod test {
         assert_eq!(&disp, exp);
     }
 
+    /// the optimised run takes as many cycles as the digital twin
+    fn assert_twin_cycles(code: &str, inp: &str) {
+        let prog = Prog::new(code, MachineConfig::default()).unwrap();
+        let mut run = crate::Run::new(code, inp, MachineConfig::default()).unwrap();
+        while !run.step().unwrap() {
+            run.pc += 1;
+        }
+        let (stats, out) = prog.run_bytes(inp.as_bytes()).unwrap();
+        assert_eq!((stats.cycles, out), (run.cycles(), run.out), "{code}");
+    }
+
     #[test]
     fn arith_loops() {
-        test_arith_loop("[->>+++<<<+>]", "{[(2, 3), (-1, 1)], 12}");
-        test_arith_loop("[->++++[->++++<]<]", "[->++++{[(1, 4)], 8}<]");
-        test_arith_loop("[<+>[-]>]", "[<+>{_}>]");
+        test_arith_loop(",[->>+++<<<+>]", "{0:,}{0:[(-1, 1), (2, 3)], 12}");
+        test_arith_loop(
+            ",[->++++[->++++<]<]",
+            "{0:,}[{0:-1}{1:+4}{1:[(1, 4)], 8}{>0}{*[(2, 16)], 41}]",
+        );
+        test_arith_loop(",[<+>[-]>]", "{0:,}[{-1:+1}{0:_}{>1}]");
+    }
+
+    #[test]
+    fn offsets() {
+        test_arith_loop(">+>-<<+>>-.<", "{1:+1}{2:-2}{0:+1}{2:.}{>1}");
+        test_arith_loop(",+<>-", "{0:,}{0:+1}{~2}{0:-1}");
+        test_arith_loop(",>>,[-]<.", "{0:,}{2:,}{2:_}{1:.}{>1}");
+    }
+
+    #[test]
+    fn scan_loops() {
+        test_arith_loop(",[>],[<<]>[>>>]", "{0:,}{[>1]}{0:,}{[<2]}{>1}{[>3]}");
+        test_arith_loop(",[><>]", "{0:,}[{~2}{>1}]");
+        for code in ["+>+>+>>+<<<<[>]<[<]>>>[>>]<[<<<]", "+[<]", "+>>+[>]"] {
+            assert_twin_cycles(code, "");
+        }
+    }
+
+    #[test]
+    fn dead_code() {
+        test_arith_loop("[->+<]>++[-]<-+>[>]", "{~6}{1:+2}{1:_}{0:-1}{0:+1}{~3}{>1}");
+        test_arith_loop("+[-]+++[<]", "{0:+1}{0:=3}{[<1]}");
+        test_arith_loop("+[-]+++[-][<]", "{0:+1}{0:=3}{0:_}{~3}");
+        test_arith_loop(",[.[-]+-[.]],", "{0:,}[{0:.}{0:_}{~5}]{0:,}");
+        test_arith_loop(">><++--<>", "{~6}{>1}");
+        // `--` would leave the range of the cell
+        test_arith_loop("+--+", "{0:+1}{0:-2}{0:+1}");
+        for code in [
+            "[->+<]>++[-]<-+>[>]",
+            "+[-]+++[<]",
+            "+[-]+++[-][<]",
+            "+[[-]+-[.]]",
+            "+[>+<-]>>,<<-+>--+",
+        ] {
+            assert_twin_cycles(code, "");
+        }
+    }
+
+    #[test]
+    fn skipped_loops() {
+        for code in [
+            ",[-]>,[->+<]>[>]+[-<+>[-]]",
+            ",[->[-]+++[->+<]<]",
+            ",[>+>[-]>[<]<<-]",
+            ",[[-]>[-]+[->,[->+<]<]<]",
+        ] {
+            assert_twin_cycles(code, "");
+            assert_twin_cycles(code, "\x03\x00\x02");
+        }
+    }
+
+    #[test]
+    fn example_programs() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../../example_programs");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let code = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+            assert_twin_cycles(&code, "60\n");
+        }
+    }
+
+    #[test]
+    fn nested_loops() {
+        let code = "++++[>++++++++[>++++<-]<-]>>.";
+        test_arith_loop(
+            code,
+            "{0:+4}[{1:+8}{1:[(1, 4)], 8}{0:-1}{>0}{*[(2, 32)], 77}]{2:.}{>2}",
+        );
+        let prog = Prog::new(code, MachineConfig::default()).unwrap();
+        let mut run = crate::Run::new(code, "", MachineConfig::default()).unwrap();
+        while !run.step().unwrap() {
+            run.pc += 1;
+        }
+        let (stats, out) = prog.run_bytes(b"").unwrap();
+        assert_eq!((stats.cycles, out), (run.cycles(), b"\x80".to_vec()));
+    }
+
+    #[test]
+    fn limits() {
+        use crate::bytecode::Bytecode;
+        let wrapping = MachineConfig {
+            tape_len: 4,
+            boundary: crate::machine::BoundaryPolicy::Wrap,
+            ..MachineConfig::default()
+        };
+        let fuel = Limits {
+            cycles: 10_000,
+            ..Limits::NONE
+        };
+        for (code, config, limits, exp) in [
+            (
+                "+.",
+                MachineConfig::default(),
+                fuel,
+                Outcome::Finished(vec![1]),
+            ),
+            (
+                "+.[]",
+                MachineConfig::default(),
+                fuel,
+                Outcome::OutOfFuel(vec![1]),
+            ),
+            // a scan without any zero cell on the tape
+            ("+>+>+>+[>]", wrapping, fuel, Outcome::OutOfFuel(vec![])),
+            (
+                "+[.]",
+                MachineConfig::default(),
+                Limits {
+                    output: 3,
+                    ..Limits::NONE
+                },
+                Outcome::OutputLimit(vec![1; 3]),
+            ),
+            (
+                "+[]",
+                MachineConfig::default(),
+                Limits {
+                    time: Some(Duration::from_millis(10)),
+                    ..Limits::NONE
+                },
+                Outcome::TimedOut(vec![]),
+            ),
+        ] {
+            let prog = Prog::new(code, config).unwrap();
+            let (_, outcome) = prog.run_with_limits(b"", limits).unwrap();
+            assert_eq!(outcome, exp, "{code}");
+            let (_, outcome) = Bytecode::new(&prog).run_with_limits(b"", limits).unwrap();
+            assert_eq!(outcome, exp, "{code}");
+        }
+        let (res, outcome) = Prog::new("+[]", MachineConfig::default())
+            .unwrap()
+            .run_with_limits(b"", fuel)
+            .unwrap();
+        assert!(!outcome.is_finished() && res.stats.cycles > fuel.cycles);
     }
 }
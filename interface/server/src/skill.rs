@@ -1,42 +1,535 @@
+use std::{slice, time::Duration};
+
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::optimized::Prog;
+use crate::{
+    bytecode::Bytecode,
+    generate::{generate, GenerateOptions},
+    machine::{MachineConfig, MachineError},
+    optimized::{Limits, Outcome, Prog},
+    UnmatchedBracket,
+};
 
-pub fn get_skill(code: &str, target: &str) -> f64 {
-    let Some(prog) = Prog::new(code) else {
-        return 0.0;
+/// limits of scoring a submission, far above anything printing a short target needs
+const SUBMISSION_LIMITS: Limits = Limits {
+    cycles: 100_000_000,
+    output: usize::MAX,
+    time: Some(Duration::from_secs(2)),
+};
+
+const CODE_LEN_WEIGHT: f64 = 8.0;
+const CYCLES_WEIGHT: f64 = 1.0;
+
+/// weights of the logs of code length and cycles, a lower weighted sum is better
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ScoringProfile {
+    pub code_len_weight: f64,
+    pub cycles_weight: f64,
+}
+
+impl ScoringProfile {
+    pub const BALANCED: Self = Self {
+        code_len_weight: CODE_LEN_WEIGHT,
+        cycles_weight: CYCLES_WEIGHT,
+    };
+    pub const SHORTEST: Self = Self {
+        code_len_weight: 1.0,
+        cycles_weight: 0.0,
     };
-    let (cycles, output) = prog.run("");
-    let out = String::from_utf8(output).unwrap_or("invalid utf-8".to_string());
-    if out == target {
-        let res = skill_value(prog.len, cycles);
-        let def_len = count_default_len(target);
-        let base = skill_value(def_len, def_len);
-        100.0 * (base - res) / base
-    } else {
-        eprintln!("wrong output: expcted {target}, got {out}");
-        0.0
-    }
-}
-
-fn count_default_len(target: &str) -> usize {
-    target
-        .chars()
-        .fold((0, 0 as char), |acc, e| {
-            (acc.0 + 1 + (acc.1 as i32 - e as i32).abs() as usize, e)
+    pub const FASTEST: Self = Self {
+        code_len_weight: 0.0,
+        cycles_weight: 1.0,
+    };
+
+    /// `balanced`, `shortest` or `fastest`
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "balanced" => Some(Self::BALANCED),
+            "shortest" => Some(Self::SHORTEST),
+            "fastest" => Some(Self::FASTEST),
+            _ => None,
+        }
+    }
+
+    /// weights are finite, not negative and not both 0
+    pub fn is_valid(&self) -> bool {
+        let weights = [self.code_len_weight, self.cycles_weight];
+        weights.iter().all(|w| w.is_finite() && *w >= 0.0) && weights.iter().any(|&w| w > 0.0)
+    }
+
+    /// weighted logs of code length and cycles
+    ///
+    /// an empty program or run counts like one command or cycle, so the terms are
+    /// never negative and stay finite
+    pub fn terms(&self, code_len: usize, cycles: usize) -> Terms {
+        Terms {
+            code_len,
+            cycles,
+            code_len_term: (code_len.max(1) as f64).ln() * self.code_len_weight,
+            cycles_term: (cycles.max(1) as f64).ln() * self.cycles_weight,
+        }
+    }
+}
+
+impl Default for ScoringProfile {
+    fn default() -> Self {
+        Self::BALANCED
+    }
+}
+
+/// a program weighted by a [`ScoringProfile`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Terms {
+    /// number of commands
+    pub code_len: usize,
+    /// summed over all cases
+    pub cycles: usize,
+    pub code_len_term: f64,
+    pub cycles_term: f64,
+}
+
+impl Terms {
+    pub fn value(&self) -> f64 {
+        self.code_len_term + self.cycles_term
+    }
+}
+
+/// a submission passing every case of a task
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Score {
+    /// how much better than the reference solution, in percent. for a target
+    /// the reference is made by [`generate`]
+    pub skill: f64,
+    /// number of commands
+    pub code_len: usize,
+    /// summed over all cases
+    pub cycles: usize,
+    pub profile: ScoringProfile,
+}
+
+/// how the skill of a submission comes about, or why it has none
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SkillReport {
+    pub profile: ScoringProfile,
+    /// the reference solution, which scores 0
+    pub baseline: Terms,
+    /// missing if the submission does not score
+    pub submission: Option<Terms>,
+    /// how much lower the weighted sum is than the one of the baseline, in percent
+    pub skill: Option<f64>,
+    pub failure: Option<Rejection>,
+}
+
+impl SkillReport {
+    fn new(
+        profile: ScoringProfile,
+        baseline: Terms,
+        res: Result<(usize, usize), Rejection>,
+    ) -> Self {
+        let (submission, failure) = match res {
+            Ok((code_len, cycles)) => (Some(profile.terms(code_len, cycles)), None),
+            Err(rejection) => (None, Some(rejection)),
+        };
+        let base = baseline.value();
+        // a baseline of a single command and cycle cannot be beaten, so every
+        // unit of weighted sum above it counts as 100 percent
+        let scale = if base > 0.0 { base } else { 1.0 };
+        Self {
+            profile,
+            baseline,
+            submission,
+            skill: submission.map(|terms| 100.0 * (base - terms.value()) / scale),
+            failure,
+        }
+    }
+
+    pub fn score(&self) -> Result<Score, Rejection> {
+        match (self.submission, self.skill, &self.failure) {
+            (Some(terms), Some(skill), _) => Ok(Score {
+                skill,
+                code_len: terms.code_len,
+                cycles: terms.cycles,
+                profile: self.profile,
+            }),
+            (_, _, Some(failure)) => Err(failure.clone()),
+            _ => unreachable!("reports have either a skill or a failure"),
+        }
+    }
+}
+
+/// why a submission does not score
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Rejection {
+    InvalidNesting {
+        unmatched: Vec<UnmatchedBracket>,
+    },
+    /// the run violated the machine model
+    Machine {
+        error: MachineError,
+    },
+    /// the run took too many cycles, with the output until then
+    OutOfFuel {
+        got: String,
+    },
+    /// the run printed more than expected
+    OutputLimit {
+        got: String,
+    },
+    TimedOut {
+        got: String,
+    },
+    WrongOutput {
+        expected: String,
+        got: String,
+        /// first char that differs
+        position: usize,
+    },
+}
+
+/// an input and the output expected for it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestCase {
+    pub input: String,
+    pub expected: String,
+}
+
+/// a task solved by passing every test case, like reversing the input
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Task {
+    pub name: String,
+    pub desc: String,
+    pub cases: Vec<TestCase>,
+    /// a solution that scores 0
+    pub reference: String,
+    #[serde(default)]
+    pub profile: ScoringProfile,
+    /// terms of the reference solution, set by [`Task::validate`]
+    #[serde(skip)]
+    baseline: Option<Terms>,
+}
+
+impl Task {
+    /// check that the profile is valid and the reference passes every case
+    ///
+    /// keeps the terms of the reference, so scoring does not run it again
+    pub fn validate(&mut self) -> Result<(), String> {
+        if !self.profile.is_valid() {
+            return Err("invalid profile".to_string());
+        }
+        let baseline = reference_terms(&self.reference, &self.cases, self.profile)
+            .map_err(|rejection| format!("reference solution fails: {rejection:?}"))?;
+        self.baseline = Some(baseline);
+        Ok(())
+    }
+}
+
+/// how a submission did on one test case
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "verdict", rename_all = "snake_case")]
+pub enum CaseReport {
+    Passed { cycles: usize },
+    Failed { rejection: Rejection },
+}
+
+/// how a submission did on a task, it only scores if it passes every case
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskReport {
+    /// fails with the rejection of the first failing case
+    pub skill: SkillReport,
+    pub cases: Vec<CaseReport>,
+}
+
+/// run a program on one case on the default machine, returns the cycles it took
+fn run_case(bytecode: &Bytecode, case: &TestCase) -> Result<usize, Rejection> {
+    // any output longer than expected is wrong anyway
+    let limits = Limits {
+        output: case.expected.len(),
+        ..SUBMISSION_LIMITS
+    };
+    let (res, outcome) = bytecode
+        .run_with_limits(case.input.as_bytes(), limits)
+        .map_err(|error| Rejection::Machine { error })?;
+    let got = String::from_utf8_lossy(outcome.output()).into_owned();
+    match outcome {
+        Outcome::Finished(_) if got == case.expected => Ok(res.stats.cycles),
+        Outcome::Finished(_) => {
+            let position = got
+                .chars()
+                .zip(case.expected.chars())
+                .take_while(|(a, b)| a == b)
+                .count();
+            Err(Rejection::WrongOutput {
+                expected: case.expected.clone(),
+                got,
+                position,
+            })
+        }
+        Outcome::OutOfFuel(_) => Err(Rejection::OutOfFuel { got }),
+        Outcome::OutputLimit(_) => Err(Rejection::OutputLimit { got }),
+        Outcome::TimedOut(_) => Err(Rejection::TimedOut { got }),
+    }
+}
+
+fn parse(code: &str) -> Result<Prog, Rejection> {
+    Prog::new(code, MachineConfig::default()).map_err(|err| Rejection::InvalidNesting {
+        unmatched: err.unmatched,
+    })
+}
+
+/// run a program on every case of a task and score it if it passes all of them
+///
+/// returns an error if the code cannot be parsed, or with the rejection of the
+/// reference solution if the task was not [validated](Task::validate) and it fails
+pub fn score_task(code: &str, task: &Task) -> Result<TaskReport, Rejection> {
+    let prog = parse(code)?;
+    let bytecode = Bytecode::new(&prog);
+    let cases: Vec<_> = task
+        .cases
+        .iter()
+        .map(|case| match run_case(&bytecode, case) {
+            Ok(cycles) => CaseReport::Passed { cycles },
+            Err(rejection) => CaseReport::Failed { rejection },
+        })
+        .collect();
+    let res = cases
+        .iter()
+        .map(|case| match case {
+            CaseReport::Passed { cycles } => Ok(cycles),
+            CaseReport::Failed { rejection } => Err(rejection.clone()),
         })
-        .0
+        .sum::<Result<usize, _>>()
+        .map(|cycles| (prog.len, cycles));
+    let baseline = match task.baseline {
+        Some(baseline) => baseline,
+        None => reference_terms(&task.reference, &task.cases, task.profile)?,
+    };
+    Ok(TaskReport {
+        skill: SkillReport::new(task.profile, baseline, res),
+        cases,
+    })
 }
 
-const CODE_LEN_WEIGHT: f64 = 8.0;
-const CYCLES_WEIGHT: f64 = 1.0;
+/// the terms of a reference solution, which has to pass every case
+fn reference_terms(
+    reference: &str,
+    cases: &[TestCase],
+    profile: ScoringProfile,
+) -> Result<Terms, Rejection> {
+    let prog = parse(reference)?;
+    let bytecode = Bytecode::new(&prog);
+    let cycles = cases
+        .iter()
+        .map(|case| run_case(&bytecode, case))
+        .sum::<Result<usize, _>>()?;
+    Ok(profile.terms(prog.len, cycles))
+}
+
+/// score a program printing `target` without input on the default machine
+pub fn skill_report(code: &str, target: &str, profile: ScoringProfile) -> SkillReport {
+    let case = TestCase {
+        input: String::new(),
+        expected: target.to_string(),
+    };
+    let baseline = reference_terms(&reference(target), slice::from_ref(&case), profile)
+        .expect("generated reference fails");
+    let res = parse(code).and_then(|prog| Ok((prog.len, run_case(&Bytecode::new(&prog), &case)?)));
+    SkillReport::new(profile, baseline, res)
+}
+
+pub fn score(code: &str, target: &str, profile: ScoringProfile) -> Result<Score, Rejection> {
+    skill_report(code, target, profile).score()
+}
+
+/// skill on the balanced profile, 0 if the submission does not score
+pub fn get_skill(code: &str, target: &str) -> f64 {
+    skill_report(code, target, ScoringProfile::BALANCED)
+        .skill
+        .unwrap_or(0.0)
+}
+
+/// the best submission of a [`Challenge`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Submission {
+    pub code: String,
+    pub score: Score,
+}
 
-pub fn skill_value(code_len: usize, cycles: usize) -> f64 {
-    (code_len as f64).ln() * CODE_LEN_WEIGHT + (cycles as f64).ln() * CYCLES_WEIGHT
+/// print a random target with as little code and as few cycles as possible
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Challenge {
+    pub id: u64,
+    pub target: String,
+    /// a solution that scores 0
+    pub reference: String,
+    pub profile: ScoringProfile,
+    pub best: Option<Submission>,
+}
+
+impl Challenge {
+    pub fn new(id: u64, target: String, profile: ScoringProfile) -> Self {
+        Self {
+            id,
+            reference: reference(&target),
+            target,
+            profile,
+            best: None,
+        }
+    }
+
+    /// keep a submission if it beats the best one, returns whether it did
+    pub fn record(&mut self, code: &str, score: Score) -> bool {
+        let best = self
+            .best
+            .as_ref()
+            .map_or(true, |best| score.skill > best.score.skill);
+        if best {
+            self.best = Some(Submission {
+                code: code.to_string(),
+                score,
+            });
+        }
+        best
+    }
+}
+
+/// the solution for printing `target` that scores 0
+pub fn reference(target: &str) -> String {
+    generate(target.as_bytes(), GenerateOptions::default())
 }
 
 pub fn generate_target() -> String {
     let mut rng = thread_rng();
     let len = rng.gen_range(10..20);
     (0..len).map(|_| rng.gen_range('!'..='~')).collect()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scores_submissions() {
+        let profile = ScoringProfile::default();
+        let mut challenge = Challenge::new(0, "AB".to_string(), profile);
+        let reference = score(&challenge.reference, &challenge.target, profile).unwrap();
+        assert_eq!(reference.skill, 0.0);
+        // printing with `+` only is worse than the reference
+        let naive = format!("{}.+.", "+".repeat(65));
+        let naive_score = score(&naive, &challenge.target, profile).unwrap();
+        assert!(naive_score.skill < 0.0);
+        assert!(challenge.record(&naive, naive_score));
+        let short = "++++++++[>++++++++<-]>+.+.";
+        let better = score(short, &challenge.target, profile).unwrap();
+        assert!(better.skill > naive_score.skill);
+        assert!(challenge.record(short, better));
+        assert!(!challenge.record(&naive, naive_score));
+        assert_eq!(challenge.best.unwrap().code, short);
+
+        assert_eq!(
+            score("++++++++[>++++++++<-]>+.++.", "AB", profile),
+            Err(Rejection::WrongOutput {
+                expected: "AB".to_string(),
+                got: "AC".to_string(),
+                position: 1,
+            })
+        );
+        assert_eq!(
+            score("+[.]", "AB", profile),
+            Err(Rejection::OutputLimit {
+                got: "\x01\x01".to_string()
+            })
+        );
+        // debug builds may hit the deadline first
+        assert!(matches!(
+            score("+[]", "AB", profile),
+            Err(Rejection::OutOfFuel { .. } | Rejection::TimedOut { .. })
+        ));
+    }
+
+    #[test]
+    fn reports_skill() {
+        let naive = format!("{}.+.", "+".repeat(65));
+        let shortest = skill_report(&naive, "AB", ScoringProfile::SHORTEST);
+        let fastest = skill_report(&naive, "AB", ScoringProfile::FASTEST);
+        // naive code is long but takes the fewest cycles
+        assert!(shortest.skill.unwrap() < 0.0);
+        assert!(fastest.skill.unwrap() > 0.0);
+        let terms = fastest.submission.unwrap();
+        assert_eq!((terms.code_len, terms.cycles), (68, 68));
+        assert_eq!((terms.code_len_term, terms.cycles_term), (0.0, 68f64.ln()));
+        assert_eq!(fastest.failure, None);
+
+        let report = skill_report("[", "AB", ScoringProfile::BALANCED);
+        assert_eq!((report.submission, report.skill), (None, None));
+        assert!(matches!(
+            report.failure,
+            Some(Rejection::InvalidNesting { .. })
+        ));
+        assert_eq!(get_skill("[", "AB"), 0.0);
+
+        // the baseline for an empty target is the empty program
+        let empty = skill_report("", "", ScoringProfile::SHORTEST);
+        assert_eq!(empty.baseline.value(), 0.0);
+        assert_eq!(empty.skill, Some(0.0));
+        let skill = skill_report("+-", "", ScoringProfile::SHORTEST)
+            .skill
+            .unwrap();
+        assert!(skill.is_finite() && skill < 0.0);
+
+        assert_eq!(
+            ScoringProfile::named("fastest"),
+            Some(ScoringProfile::FASTEST)
+        );
+        assert!(!ScoringProfile {
+            code_len_weight: 0.0,
+            cycles_weight: 0.0
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn scores_tasks() {
+        let mut tasks: Vec<Task> = serde_json::from_str(include_str!("../tasks.json")).unwrap();
+        for task in &mut tasks {
+            task.validate().unwrap();
+            let report = score_task(&task.reference, task).unwrap();
+            assert_eq!(report.skill.skill, Some(0.0), "{}", task.name);
+        }
+        let add = tasks.iter().find(|task| task.name == "add").unwrap();
+        let report = score_task(",>,[-<+>]++++++[-<-------->]<.", add).unwrap();
+        assert!(report.skill.skill.unwrap() > 0.0);
+
+        // cat passes the palindromes only
+        let reverse = tasks.iter().find(|task| task.name == "reverse").unwrap();
+        let report = score_task(",[.,]", reverse).unwrap();
+        assert_eq!(report.skill.skill, None);
+        let passed = report
+            .cases
+            .iter()
+            .map(|case| matches!(case, CaseReport::Passed { .. }))
+            .collect::<Vec<_>>();
+        assert_eq!(passed, [false, false, true, false, true]);
+        assert_eq!(
+            report.cases[0],
+            CaseReport::Failed {
+                rejection: Rejection::WrongOutput {
+                    expected: "cba".to_string(),
+                    got: "abc".to_string(),
+                    position: 0,
+                }
+            }
+        );
+        assert!(score_task("[", reverse).is_err());
+
+        // a reference failing its own cases is caught when validating
+        let mut broken = reverse.clone();
+        broken.reference = ",[.,]".to_string();
+        broken.baseline = None;
+        assert!(broken.validate().is_err());
+        assert!(score_task(",[.,]", &broken).is_err());
+        broken.reference = "[".to_string();
+        assert!(broken.validate().is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use super::{check_nesting, machine::MachineConfig, ParseError, Run};
+
+/// current version of the snapshot format
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// a saved session
+///
+/// holds the code, input and output of the server,
+/// plus the state of the [digital twin](crate::Run) if a run was active
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub code: String,
+    pub input: String,
+    pub output: String,
+    pub run: Option<RunSnapshot>,
+}
+
+impl Snapshot {
+    pub fn new(code: String, input: String, output: String, run: Option<RunSnapshot>) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            code,
+            input,
+            output,
+            run,
+        }
+    }
+
+    /// restore the saved run, if there was one
+    pub fn restore_run(&self) -> Result<Option<Run>, SnapshotError> {
+        if self.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(self.version));
+        }
+        check_nesting(&self.code)?;
+        self.run
+            .as_ref()
+            .map(|state| Run::restore(&self.code, &self.input, state))
+            .transpose()
+    }
+}
+
+/// the state of a [`Run`](crate::Run)
+///
+/// code and input are not included, they are part of the [`Snapshot`]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct RunSnapshot {
+    pub machine: MachineConfig,
+    /// run-length encoded as `(value, count)` pairs
+    pub tape: Vec<(u32, usize)>,
+    pub pc: usize,
+    pub ic: usize,
+    pub head: usize,
+    pub jumping: Option<usize>,
+    pub stack: Vec<usize>,
+    pub out: Vec<u8>,
+    pub cycles: usize,
+}
+
+/// a snapshot cannot be restored
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SnapshotError {
+    /// written by an unknown version of the format
+    UnsupportedVersion(u32),
+    /// the saved code does not parse
+    InvalidCode(ParseError),
+    /// the saved run does not fit the code or machine config
+    InvalidState(&'static str),
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "unsupported snapshot version {v}, expected {SNAPSHOT_VERSION}")
+            }
+            SnapshotError::InvalidCode(err) => write!(f, "{err}"),
+            SnapshotError::InvalidState(reason) => write!(f, "invalid run state: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<ParseError> for SnapshotError {
+    fn from(value: ParseError) -> Self {
+        SnapshotError::InvalidCode(value)
+    }
+}
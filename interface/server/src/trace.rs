@@ -0,0 +1,364 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::{self, Read, Write},
+};
+
+use serde::Serialize;
+
+use super::{
+    machine::{BoundaryPolicy, MachineConfig},
+    parse_with_source_map, BFCommand, CodeView, Run, RunView, SourceMap,
+};
+
+/// magic bytes at the start of every trace
+const MAGIC: &[u8; 4] = b"BFTR";
+/// current version of the trace format
+pub const TRACE_VERSION: u8 = 1;
+/// longest source a trace can hold
+pub const MAX_SOURCE_LEN: usize = 1 << 24;
+
+/// io caused by a single step
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum IoEvent {
+    /// `,` consumed a value of the input
+    In(u32),
+    /// `.` wrote a byte
+    Out(u8),
+}
+
+/// a single executed step
+///
+/// `head` is the head after the step, `before` and `after` are the value of the cell
+/// under it before and after the step. as only the cell under the head is ever written,
+/// this is enough to rebuild the whole tape
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+pub struct TraceRecord {
+    pub pc: usize,
+    pub head: usize,
+    pub before: u32,
+    pub after: u32,
+    pub jumping: Option<usize>,
+    pub io: Option<IoEvent>,
+}
+
+fn write_varint(w: &mut impl Write, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return w.write_all(&[byte]);
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+/// read a varint, returns `None` on a clean end of the stream
+fn read_varint(r: &mut impl Read) -> io::Result<Option<u64>> {
+    let mut value = 0_u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        if r.read(&mut byte)? == 0 {
+            return if shift == 0 {
+                Ok(None)
+            } else {
+                Err(io::ErrorKind::UnexpectedEof.into())
+            };
+        }
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "varint too long",
+    ))
+}
+
+fn expect_varint(r: &mut impl Read) -> io::Result<u64> {
+    read_varint(r)?.ok_or(io::ErrorKind::UnexpectedEof.into())
+}
+
+/// writes a trace
+///
+/// format: `BFTR`, version byte, source length + source, tape length, whether the
+/// tape wraps (0 or 1), then one record per step. all numbers are LEB128 varints, a record is
+/// `pc head before after jumping io [value]` with `jumping = 0` for not jumping
+/// and `io` being 0 (none), 1 (input) or 2 (output)
+pub struct TraceWriter {
+    writer: Box<dyn Write + Send + Sync>,
+}
+
+impl Debug for TraceWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TraceWriter").finish_non_exhaustive()
+    }
+}
+
+impl TraceWriter {
+    /// start a trace of `source` running on a machine with `config`
+    pub fn new(
+        writer: impl Write + Send + Sync + 'static,
+        source: &str,
+        config: &MachineConfig,
+    ) -> io::Result<Self> {
+        let mut writer: Box<dyn Write + Send + Sync> = Box::new(writer);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[TRACE_VERSION])?;
+        write_varint(&mut writer, source.len() as u64)?;
+        writer.write_all(source.as_bytes())?;
+        write_varint(&mut writer, config.tape_len as u64)?;
+        let wraps = config.boundary == BoundaryPolicy::Wrap;
+        write_varint(&mut writer, wraps as u64)?;
+        Ok(Self { writer })
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let w = &mut self.writer;
+        write_varint(w, record.pc as u64)?;
+        write_varint(w, record.head as u64)?;
+        write_varint(w, record.before as u64)?;
+        write_varint(w, record.after as u64)?;
+        write_varint(w, record.jumping.unwrap_or(0) as u64)?;
+        match record.io {
+            None => write_varint(w, 0),
+            Some(IoEvent::In(value)) => {
+                write_varint(w, 1)?;
+                write_varint(w, value as u64)
+            }
+            Some(IoEvent::Out(value)) => {
+                write_varint(w, 2)?;
+                write_varint(w, value as u64)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// reads a trace written by [`TraceWriter`]
+///
+/// iterates over all records
+pub struct TraceReader<R> {
+    reader: R,
+    source: String,
+    tape_len: usize,
+    wraps: bool,
+}
+
+impl<R: Read> TraceReader<R> {
+    /// read the header of a trace
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a trace"));
+        }
+        let mut version = [0];
+        reader.read_exact(&mut version)?;
+        if version[0] != TRACE_VERSION {
+            return Err(invalid("unsupported trace version"));
+        }
+        let len = expect_varint(&mut reader)?;
+        if len > MAX_SOURCE_LEN as u64 {
+            return Err(invalid("source too long"));
+        }
+        let mut source = vec![0; len as usize];
+        reader.read_exact(&mut source)?;
+        let source = String::from_utf8(source).map_err(|_| invalid("source is not utf-8"))?;
+        let tape_len = expect_varint(&mut reader)?;
+        let wraps = match expect_varint(&mut reader)? {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid("invalid boundary")),
+        };
+        if wraps && tape_len == 0 {
+            return Err(invalid("wrapping tape without cells"));
+        }
+        Ok(Self {
+            reader,
+            source,
+            tape_len: tape_len as usize,
+            wraps,
+        })
+    }
+
+    /// the traced source
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// length of the tape at the start of the run
+    pub fn tape_len(&self) -> usize {
+        self.tape_len
+    }
+
+    /// whether the head wraps around the ends of the tape
+    pub fn wraps(&self) -> bool {
+        self.wraps
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        let Some(pc) = read_varint(&mut self.reader)? else {
+            return Ok(None);
+        };
+        let r = &mut self.reader;
+        let head = expect_varint(r)? as usize;
+        let before = expect_varint(r)? as u32;
+        let after = expect_varint(r)? as u32;
+        let jumping = match expect_varint(r)? {
+            0 => None,
+            depth => Some(depth as usize),
+        };
+        let io = match expect_varint(r)? {
+            0 => None,
+            1 => Some(IoEvent::In(expect_varint(r)? as u32)),
+            2 => Some(IoEvent::Out(expect_varint(r)? as u8)),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid io event",
+                ))
+            }
+        };
+        Ok(Some(TraceRecord {
+            pc: pc as usize,
+            head,
+            before,
+            after,
+            jumping,
+            io,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// plays back a trace, rebuilding the state of the [digital twin](Run) step by step
+pub struct TraceReplay {
+    reader: TraceReader<Box<dyn Read + Send + Sync>>,
+    code: Vec<BFCommand>,
+    source_map: SourceMap,
+    /// sparse tape, unseen cells are 0
+    tape: HashMap<usize, u32>,
+    pc: usize,
+    head: usize,
+    jumping: Option<usize>,
+    stack: Vec<usize>,
+    ic: usize,
+    pub out: Vec<u8>,
+    cycles: usize,
+}
+
+impl Debug for TraceReplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TraceReplay")
+            .field("pc", &self.pc)
+            .field("head", &self.head)
+            .field("cycles", &self.cycles)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TraceReplay {
+    pub fn new(reader: impl Read + Send + Sync + 'static) -> io::Result<Self> {
+        let reader: Box<dyn Read + Send + Sync> = Box::new(reader);
+        let reader = TraceReader::new(reader)?;
+        let (code, source_map) = parse_with_source_map(reader.source())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(Self {
+            reader,
+            code,
+            source_map,
+            tape: HashMap::new(),
+            pc: 0,
+            head: 0,
+            jumping: None,
+            stack: Vec::new(),
+            ic: 0,
+            out: Vec::new(),
+            cycles: 0,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        self.reader.source()
+    }
+
+    /// apply the next record
+    ///
+    /// returns false at the end of the trace
+    pub fn step(&mut self) -> io::Result<bool> {
+        let Some(record) = self.reader.next().transpose()? else {
+            return Ok(false);
+        };
+        let Some(&cmd) = self.code.get(record.pc) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "pc outside of code",
+            ));
+        };
+        if self.jumping.is_none() {
+            match cmd {
+                BFCommand::LoopStart if record.jumping.is_none() => self.stack.push(record.pc),
+                BFCommand::LoopEnd if record.after == 0 => {
+                    self.stack.pop();
+                }
+                _ => {}
+            }
+        }
+        match record.io {
+            Some(IoEvent::In(_)) => self.ic += 1,
+            Some(IoEvent::Out(value)) => self.out.push(value),
+            None => {}
+        }
+        self.pc = record.pc;
+        self.head = record.head;
+        self.tape.insert(record.head, record.after);
+        self.jumping = record.jumping;
+        self.cycles += 1;
+        Ok(true)
+    }
+
+    pub fn view(&self, ctrl_state: &str, run_state: &str) -> RunView {
+        let tape = (self.head as isize - Run::VIEW_SIZE as isize
+            ..=self.head as isize + Run::VIEW_SIZE as isize)
+            .map(|i| {
+                // wrap like `Tape::window`, cells outside of a non-wrapping tape are 0
+                let i = match self.reader.wraps() {
+                    true => Some(i.rem_euclid(self.reader.tape_len() as isize) as usize),
+                    false => usize::try_from(i).ok(),
+                };
+                i.and_then(|i| self.tape.get(&i)).copied().unwrap_or(0)
+            })
+            .collect();
+        RunView {
+            tape,
+            head: self.head,
+            code: CodeView::new(&self.code, &self.source_map, self.pc),
+            ic: self.ic,
+            jumping: self.jumping,
+            stack: self.stack.clone(),
+            cycles: self.cycles,
+            control_state: ctrl_state.to_string(),
+            run_state: self
+                .jumping
+                .map(|_| "jumping")
+                .unwrap_or(run_state)
+                .to_string(),
+            pause_reason: None,
+            registers: None,
+        }
+    }
+}
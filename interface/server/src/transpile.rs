@@ -0,0 +1,425 @@
+use std::fmt::Write as _;
+
+use serde::Deserialize;
+
+use super::{
+    machine::{BoundaryPolicy, CellWidth, EofPolicy, OverflowPolicy, MAX_TAPE_LEN},
+    optimized::{BFCommandOpt, Prog},
+};
+
+/// language of a transpiled program
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    C,
+    Rust,
+}
+
+/// standalone source of an [optimised program](Prog)
+///
+/// the generated program reads stdin, writes stdout and follows the
+/// [`MachineConfig`](crate::machine::MachineConfig) of the program. errors of the machine model
+/// are reported on stderr with exit code 1
+pub fn transpile(prog: &Prog, target: Target) -> String {
+    match target {
+        Target::C => to_c(prog),
+        Target::Rust => to_rust(prog),
+    }
+}
+
+pub fn to_c(prog: &Prog) -> String {
+    let config = prog.config;
+    let mut res = format!(
+        "#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef {cell} cell;
+#define CELL_MAX {max}LL
+#define MAX_LEN {max_len}LL
+
+#define FAIL(...) do {{ \\
+    fflush(stdout); \\
+    fprintf(stderr, __VA_ARGS__); \\
+    fputc('\\n', stderr); \\
+    exit(1); \\
+}} while (0)
+
+static cell *tape;
+static int64_t len = {len};
+
+static inline int64_t at(int64_t head, int64_t by) {{
+    int64_t target = head + by;
+{at}
+}}
+
+static inline void add(int64_t idx, int64_t by) {{
+    int64_t value = (int64_t)tape[idx] + by;
+{add}
+}}
+
+static inline void input(int64_t idx) {{
+    int c = getchar();
+    if (c != EOF) {{
+        tape[idx] = c;
+        return;
+    }}
+{eof}
+}}
+
+int main(void) {{
+    tape = calloc(len, sizeof(cell));
+    if (!tape) FAIL(\"out of memory\");
+    int64_t h = 0;
+",
+        cell = match config.cell_width {
+            CellWidth::U8 => "uint8_t",
+            CellWidth::U16 => "uint16_t",
+            CellWidth::U32 => "uint32_t",
+        },
+        max = config.cell_width.max(),
+        max_len = MAX_TAPE_LEN,
+        len = config.tape_len,
+        at = match config.boundary {
+            BoundaryPolicy::Wrap => "    return (target % len + len) % len;",
+            BoundaryPolicy::Error => {
+                "    if (target < 0 || target >= len) FAIL(\"data pointer left the tape at %lld\", (long long)target);
+    return target;"
+            }
+            BoundaryPolicy::Grow => {
+                "    if (target < 0) FAIL(\"data pointer left the tape at %lld\", (long long)target);
+    if (target >= len) {
+        if (target >= MAX_LEN) FAIL(\"data pointer left the tape at %lld\", (long long)target);
+        cell *grown = realloc(tape, (target + 1) * sizeof(cell));
+        if (!grown) FAIL(\"out of memory\");
+        tape = grown;
+        memset(tape + len, 0, (target + 1 - len) * sizeof(cell));
+        len = target + 1;
+    }
+    return target;"
+            }
+        },
+        add = match config.overflow {
+            OverflowPolicy::Wrap => "    tape[idx] = (cell)(value & CELL_MAX);",
+            OverflowPolicy::Saturate => {
+                "    tape[idx] = value < 0 ? 0 : value > CELL_MAX ? CELL_MAX : value;"
+            }
+            OverflowPolicy::Error => {
+                "    if (value < 0 || value > CELL_MAX) FAIL(\"cell %lld overflowed\", (long long)idx);
+    tape[idx] = value;"
+            }
+        },
+        eof = match config.eof {
+            EofPolicy::Zero => "    tape[idx] = 0;",
+            EofPolicy::Max => "    tape[idx] = CELL_MAX;",
+            EofPolicy::Unchanged => "",
+            EofPolicy::Block => "    FAIL(\"waiting for input that never arrives\");",
+        },
+    );
+    let mut depth = 1;
+    for op in &prog.code {
+        c_op(&mut res, op, &mut depth).expect("writing to a string cannot fail");
+    }
+    res.push_str("    return 0;\n}\n");
+    res
+}
+
+fn c_op(res: &mut String, op: &BFCommandOpt, depth: &mut usize) -> std::fmt::Result {
+    let indent = "    ".repeat(*depth);
+    match *op {
+        BFCommandOpt::Add(offset, by) => writeln!(res, "{indent}add(at(h, {offset}), {by});"),
+        BFCommandOpt::Move(by, _) => writeln!(res, "{indent}h = at(h, {by});"),
+        BFCommandOpt::In(offset) => writeln!(res, "{indent}input(at(h, {offset}));"),
+        BFCommandOpt::Out(offset) => {
+            writeln!(
+                res,
+                "{indent}putchar((unsigned char)tape[at(h, {offset})]);"
+            )
+        }
+        BFCommandOpt::LoopStart => {
+            *depth += 1;
+            writeln!(res, "{indent}while (tape[h]) {{")
+        }
+        BFCommandOpt::LoopEnd => {
+            *depth -= 1;
+            writeln!(res, "{}}}", "    ".repeat(*depth))
+        }
+        BFCommandOpt::SetZero(offset) => writeln!(res, "{indent}tape[at(h, {offset})] = 0;"),
+        BFCommandOpt::Set(offset, value) => {
+            writeln!(res, "{indent}tape[at(h, {offset})] = {value};")
+        }
+        BFCommandOpt::ArithLoop(offset, ref parts, ..) => c_loop(res, &indent, offset, parts),
+        BFCommandOpt::Scan(stride) => {
+            writeln!(res, "{indent}while (tape[h]) h = at(h, {stride});")
+        }
+        BFCommandOpt::Repeat(ref parts, _) => c_loop(res, &indent, 0, parts),
+        BFCommandOpt::Nop(_) => Ok(()),
+    }
+}
+
+fn c_loop(
+    res: &mut String,
+    indent: &str,
+    offset: isize,
+    parts: &[(isize, i64)],
+) -> std::fmt::Result {
+    writeln!(res, "{indent}{{")?;
+    writeln!(res, "{indent}    int64_t ctr = at(h, {offset});")?;
+    writeln!(res, "{indent}    if (tape[ctr]) {{")?;
+    for (part, by) in parts {
+        writeln!(
+            res,
+            "{indent}        add(at(ctr, {part}), {by} * (int64_t)tape[ctr]);"
+        )?;
+    }
+    writeln!(res, "{indent}        tape[ctr] = 0;")?;
+    writeln!(res, "{indent}    }}")?;
+    writeln!(res, "{indent}}}")
+}
+
+pub fn to_rust(prog: &Prog) -> String {
+    let config = prog.config;
+    let mut res = format!(
+        "#![allow(unused)]
+use std::io::{{Read, Write}};
+
+type Cell = {cell};
+const CELL_MAX: i64 = {max};
+const MAX_LEN: i64 = {max_len};
+
+fn fail(msg: String) -> ! {{
+    std::io::stdout().flush().ok();
+    eprintln!(\"{{msg}}\");
+    std::process::exit(1)
+}}
+
+struct Machine {{
+    tape: Vec<Cell>,
+    input: std::io::Bytes<std::io::StdinLock<'static>>,
+}}
+
+impl Machine {{
+    fn at(&mut self, head: usize, by: i64) -> usize {{
+        let target = head as i64 + by;
+        let len = self.tape.len() as i64;
+{at}
+    }}
+
+    fn add(&mut self, idx: usize, by: i64) {{
+        let value = self.tape[idx] as i64 + by;
+{add}
+    }}
+
+    fn input(&mut self, idx: usize) {{
+        match self.input.next() {{
+            Some(Ok(byte)) => self.tape[idx] = byte as Cell,
+            _ => {{{eof}}}
+        }}
+    }}
+}}
+
+fn main() {{
+    let mut m = Machine {{
+        tape: vec![0; {len}],
+        input: std::io::stdin().lock().bytes(),
+    }};
+    let mut out = std::io::stdout().lock();
+    let mut h = 0_usize;
+",
+        cell = match config.cell_width {
+            CellWidth::U8 => "u8",
+            CellWidth::U16 => "u16",
+            CellWidth::U32 => "u32",
+        },
+        max = config.cell_width.max(),
+        max_len = MAX_TAPE_LEN,
+        len = config.tape_len,
+        at = match config.boundary {
+            BoundaryPolicy::Wrap => "        target.rem_euclid(len) as usize",
+            BoundaryPolicy::Error => {
+                "        if !(0..len).contains(&target) {
+            fail(format!(\"data pointer left the tape at {target}\"));
+        }
+        target as usize"
+            }
+            BoundaryPolicy::Grow => {
+                "        if target < 0 {
+            fail(format!(\"data pointer left the tape at {target}\"));
+        }
+        if target >= MAX_LEN {
+            fail(format!(\"data pointer left the tape at {target}\"));
+        }
+        if target >= len {
+            self.tape.resize(target as usize + 1, 0);
+        }
+        target as usize"
+            }
+        },
+        add = match config.overflow {
+            OverflowPolicy::Wrap =>
+                "        self.tape[idx] = value.rem_euclid(CELL_MAX + 1) as Cell;",
+            OverflowPolicy::Saturate =>
+                "        self.tape[idx] = value.clamp(0, CELL_MAX) as Cell;",
+            OverflowPolicy::Error => {
+                "        if !(0..=CELL_MAX).contains(&value) {
+            fail(format!(\"cell {idx} overflowed\"));
+        }
+        self.tape[idx] = value as Cell;"
+            }
+        },
+        eof = match config.eof {
+            EofPolicy::Zero => "self.tape[idx] = 0",
+            EofPolicy::Max => "self.tape[idx] = CELL_MAX as Cell",
+            EofPolicy::Unchanged => "",
+            EofPolicy::Block => "fail(\"waiting for input that never arrives\".to_string())",
+        },
+    );
+    let mut depth = 1;
+    for op in &prog.code {
+        rust_op(&mut res, op, &mut depth).expect("writing to a string cannot fail");
+    }
+    res.push_str("    out.flush().unwrap();\n}\n");
+    res
+}
+
+fn rust_op(res: &mut String, op: &BFCommandOpt, depth: &mut usize) -> std::fmt::Result {
+    let indent = "    ".repeat(*depth);
+    match *op {
+        BFCommandOpt::Add(offset, by) => {
+            writeln!(res, "{indent}let i = m.at(h, {offset});")?;
+            writeln!(res, "{indent}m.add(i, {by});")
+        }
+        BFCommandOpt::Move(by, _) => writeln!(res, "{indent}h = m.at(h, {by});"),
+        BFCommandOpt::In(offset) => {
+            writeln!(res, "{indent}let i = m.at(h, {offset});")?;
+            writeln!(res, "{indent}m.input(i);")
+        }
+        BFCommandOpt::Out(offset) => {
+            writeln!(res, "{indent}let i = m.at(h, {offset});")?;
+            writeln!(res, "{indent}out.write_all(&[m.tape[i] as u8]).unwrap();")
+        }
+        BFCommandOpt::LoopStart => {
+            *depth += 1;
+            writeln!(res, "{indent}while m.tape[h] != 0 {{")
+        }
+        BFCommandOpt::LoopEnd => {
+            *depth -= 1;
+            writeln!(res, "{}}}", "    ".repeat(*depth))
+        }
+        BFCommandOpt::SetZero(offset) => {
+            writeln!(res, "{indent}let i = m.at(h, {offset});")?;
+            writeln!(res, "{indent}m.tape[i] = 0;")
+        }
+        BFCommandOpt::Set(offset, value) => {
+            writeln!(res, "{indent}let i = m.at(h, {offset});")?;
+            writeln!(res, "{indent}m.tape[i] = {value};")
+        }
+        BFCommandOpt::ArithLoop(offset, ref parts, ..) => rust_loop(res, &indent, offset, parts),
+        BFCommandOpt::Scan(stride) => {
+            writeln!(res, "{indent}while m.tape[h] != 0 {{")?;
+            writeln!(res, "{indent}    h = m.at(h, {stride});")?;
+            writeln!(res, "{indent}}}")
+        }
+        BFCommandOpt::Repeat(ref parts, _) => rust_loop(res, &indent, 0, parts),
+        BFCommandOpt::Nop(_) => Ok(()),
+    }
+}
+
+fn rust_loop(
+    res: &mut String,
+    indent: &str,
+    offset: isize,
+    parts: &[(isize, i64)],
+) -> std::fmt::Result {
+    writeln!(res, "{indent}let ctr = m.at(h, {offset});")?;
+    writeln!(res, "{indent}if m.tape[ctr] != 0 {{")?;
+    writeln!(res, "{indent}    let n = m.tape[ctr] as i64;")?;
+    for (part, by) in parts {
+        writeln!(res, "{indent}    let i = m.at(ctr, {part});")?;
+        writeln!(res, "{indent}    m.add(i, {by} * n);")?;
+    }
+    writeln!(res, "{indent}    m.tape[ctr] = 0;")?;
+    writeln!(res, "{indent}}}")
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    use super::*;
+    use crate::{machine::MachineConfig, Run};
+
+    /// compile and run the generated source, `None` without the compiler
+    fn compile_and_run(prog: &Prog, target: Target, inp: &str) -> Option<Vec<u8>> {
+        let dir = std::env::temp_dir().join(format!("bf_itp_transpile_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (src, compiler) = match target {
+            Target::C => (dir.join("prog.c"), "cc"),
+            Target::Rust => (dir.join("prog.rs"), "rustc"),
+        };
+        let bin = dir.join(format!("prog_{target:?}"));
+        std::fs::write(&src, transpile(prog, target)).unwrap();
+        let status = Command::new(compiler)
+            .arg("-o")
+            .arg(&bin)
+            .arg(&src)
+            .status()
+            .ok()?;
+        assert!(status.success(), "failed to compile {target:?}");
+        let mut child = Command::new(&bin)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(inp.as_bytes())
+            .unwrap();
+        let out = child.wait_with_output().unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        Some(out.stdout)
+    }
+
+    #[test]
+    fn native_output() {
+        let grow = MachineConfig {
+            tape_len: 4,
+            cell_width: CellWidth::U16,
+            boundary: BoundaryPolicy::Grow,
+            eof: EofPolicy::Max,
+            ..Default::default()
+        };
+        // moves and loops wrap around both ends of the tape
+        let wrap = MachineConfig {
+            tape_len: 4,
+            ..Default::default()
+        };
+        let unchanged = MachineConfig {
+            cell_width: CellWidth::U32,
+            eof: EofPolicy::Unchanged,
+            ..Default::default()
+        };
+        for (config, code, inp) in [
+            (grow, ",[->+>+++<<]>>>>>>,<<<[-]++<<.>.>.>>>.>>.", "\x05"),
+            (wrap, ",[-<+++>]<.<<<<.>>,.,.>>>>>+[-<+>>+<]<.>>.", "\x02a"),
+            (unchanged, "+++,.,.[-]-.", ""),
+        ] {
+            let prog = Prog::new(code, config).unwrap();
+            let mut run = Run::new(code, inp, config).unwrap();
+            while !run.step().unwrap() {
+                run.pc += 1;
+            }
+            for target in [Target::C, Target::Rust] {
+                if let Some(out) = compile_and_run(&prog, target, inp) {
+                    assert_eq!(out, run.out, "{target:?} {code}");
+                }
+            }
+        }
+    }
+}